@@ -1,7 +1,21 @@
+use std::{fmt::Write, io::Cursor};
+
 use anyrender::{NormalizedCoord, Paint, PaintRef, PaintScene};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use image::{ImageFormat as EncodedImageFormat, RgbaImage};
 use vello::{
-	kurbo::{Affine, Rect, Shape, Stroke},
-	peniko::{BlendMode, BrushRef, Color, Fill, FontData, StyleRef},
+	kurbo::{Affine, BezPath, Cap, Join, Rect, RoundedRect, Shape, Stroke},
+	peniko::{
+		BlendMode, BrushRef, Color, Extend, Fill, FontData, GradientKind, ImageAlphaType,
+		ImageFormat, Mix, StyleRef,
+		color::{AlphaColor, Srgb},
+	},
+	skrifa::{
+		FontRef, GlyphId, MetadataProvider,
+		instance::{LocationRef, Size},
+		outline::{DrawSettings, OutlinePen},
+		raw::types::F2Dot14,
+	},
 };
 
 pub struct VelloScenePainter<'s> {
@@ -117,3 +131,529 @@ impl PaintScene for VelloScenePainter<'_> {
 			.draw_blurred_rounded_rect(transform, rect, brush, radius, std_dev);
 	}
 }
+
+/// Forwards every draw call to another [`PaintScene`] with `transform` applied on top.
+pub struct TransformedScenePainter<'s, S: PaintScene> {
+	inner: &'s mut S,
+	transform: Affine,
+}
+
+impl<S: PaintScene> TransformedScenePainter<'_, S> {
+	pub fn new(scene: &mut S, transform: Affine) -> TransformedScenePainter<'_, S> {
+		TransformedScenePainter {
+			inner: scene,
+			transform,
+		}
+	}
+}
+
+impl<S: PaintScene> PaintScene for TransformedScenePainter<'_, S> {
+	fn reset(&mut self) {
+		self.inner.reset();
+	}
+
+	fn push_layer(
+		&mut self,
+		blend: impl Into<BlendMode>,
+		alpha: f32,
+		transform: Affine,
+		clip: &impl Shape,
+	) {
+		self.inner
+			.push_layer(blend, alpha, self.transform * transform, clip);
+	}
+
+	fn push_clip_layer(&mut self, transform: Affine, clip: &impl Shape) {
+		self.inner.push_clip_layer(self.transform * transform, clip);
+	}
+
+	fn pop_layer(&mut self) {
+		self.inner.pop_layer();
+	}
+
+	fn stroke<'a>(
+		&mut self,
+		style: &Stroke,
+		transform: Affine,
+		paint_ref: impl Into<PaintRef<'a>>,
+		brush_transform: Option<Affine>,
+		shape: &impl Shape,
+	) {
+		self.inner.stroke(
+			style,
+			self.transform * transform,
+			paint_ref,
+			brush_transform,
+			shape,
+		);
+	}
+
+	fn fill<'a>(
+		&mut self,
+		style: Fill,
+		transform: Affine,
+		paint: impl Into<PaintRef<'a>>,
+		brush_transform: Option<Affine>,
+		shape: &impl Shape,
+	) {
+		self.inner.fill(
+			style,
+			self.transform * transform,
+			paint,
+			brush_transform,
+			shape,
+		);
+	}
+
+	fn draw_glyphs<'a, 's: 'a>(
+		&'a mut self,
+		font: &'a FontData,
+		font_size: f32,
+		hint: bool,
+		normalized_coords: &'a [NormalizedCoord],
+		style: impl Into<StyleRef<'a>>,
+		paint: impl Into<PaintRef<'a>>,
+		brush_alpha: f32,
+		transform: Affine,
+		glyph_transform: Option<Affine>,
+		glyphs: impl Iterator<Item = anyrender::Glyph>,
+	) {
+		self.inner.draw_glyphs(
+			font,
+			font_size,
+			hint,
+			normalized_coords,
+			style,
+			paint,
+			brush_alpha,
+			self.transform * transform,
+			glyph_transform,
+			glyphs,
+		);
+	}
+
+	fn draw_box_shadow(
+		&mut self,
+		transform: Affine,
+		rect: Rect,
+		brush: Color,
+		radius: f64,
+		std_dev: f64,
+	) {
+		self.inner
+			.draw_box_shadow(self.transform * transform, rect, brush, radius, std_dev);
+	}
+}
+
+struct BezPathPen(BezPath);
+
+// font outlines are y-up, scenes are y-down
+impl OutlinePen for BezPathPen {
+	fn move_to(&mut self, x: f32, y: f32) {
+		self.0.move_to((x as f64, -y as f64));
+	}
+
+	fn line_to(&mut self, x: f32, y: f32) {
+		self.0.line_to((x as f64, -y as f64));
+	}
+
+	fn quad_to(&mut self, cx0: f32, cy0: f32, x: f32, y: f32) {
+		self.0
+			.quad_to((cx0 as f64, -cy0 as f64), (x as f64, -y as f64));
+	}
+
+	fn curve_to(&mut self, cx0: f32, cy0: f32, cx1: f32, cy1: f32, x: f32, y: f32) {
+		self.0.curve_to(
+			(cx0 as f64, -cy0 as f64),
+			(cx1 as f64, -cy1 as f64),
+			(x as f64, -y as f64),
+		);
+	}
+
+	fn close(&mut self) {
+		self.0.close_path();
+	}
+}
+
+/// Outlines a glyph run into a single path, for backends that can't draw text from font data.
+pub(crate) fn glyph_run_path(
+	font: &FontData,
+	font_size: f32,
+	normalized_coords: &[NormalizedCoord],
+	glyph_transform: Option<Affine>,
+	glyphs: impl Iterator<Item = anyrender::Glyph>,
+) -> BezPath {
+	let mut path = BezPath::new();
+	let Ok(font_ref) = FontRef::from_index(font.data.data(), font.index) else {
+		return path;
+	};
+	let outlines = font_ref.outline_glyphs();
+	let coords: Vec<F2Dot14> = normalized_coords
+		.iter()
+		.map(|x| F2Dot14::from_bits(*x))
+		.collect();
+	let settings = DrawSettings::unhinted(Size::new(font_size), LocationRef::new(&coords));
+	let glyph_transform = glyph_transform.unwrap_or_default();
+
+	for glyph in glyphs {
+		let Some(outline) = outlines.get(GlyphId::new(glyph.id)) else {
+			continue;
+		};
+		let mut pen = BezPathPen(BezPath::new());
+		if outline.draw(settings, &mut pen).is_err() {
+			continue;
+		}
+		pen.0
+			.apply_affine(Affine::translate((glyph.x as f64, glyph.y as f64)) * glyph_transform);
+		path.extend(pen.0);
+	}
+
+	path
+}
+
+/// Records a scene as SVG markup instead of rasterizing it.
+#[derive(Default)]
+pub struct SvgScenePainter {
+	defs: String,
+	body: String,
+	next_id: usize,
+}
+
+impl SvgScenePainter {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Wraps the recorded scene in an `<svg>` element. The scene is `width` by `height` device
+	/// pixels, displayed at `display_width` by `display_height`.
+	pub fn finish(
+		self,
+		width: u32,
+		height: u32,
+		display_width: f32,
+		display_height: f32,
+	) -> String {
+		format!(
+			"<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{display_width}\" height=\"{display_height}\" viewBox=\"0 0 {width} {height}\"><defs>{}</defs>{}</svg>",
+			self.defs, self.body
+		)
+	}
+
+	fn id(&mut self, prefix: &str) -> String {
+		self.next_id += 1;
+		format!("{prefix}{}", self.next_id)
+	}
+
+	fn matrix(transform: Affine) -> String {
+		let [a, b, c, d, e, f] = transform.as_coeffs();
+		format!("matrix({a} {b} {c} {d} {e} {f})")
+	}
+
+	fn color(color: Color) -> (String, f32) {
+		let rgba = color.to_rgba8();
+		(
+			format!("#{:02x}{:02x}{:02x}", rgba.r, rgba.g, rgba.b),
+			color.components[3],
+		)
+	}
+
+	/// Returns the value for a `fill` or `stroke` attribute along with its opacity.
+	fn paint(&mut self, paint: PaintRef<'_>, brush_transform: Option<Affine>) -> (String, f32) {
+		let transform = brush_transform
+			.map(|x| format!(" gradientTransform=\"{}\"", Self::matrix(x)))
+			.unwrap_or_default();
+
+		match paint {
+			Paint::Solid(color) => Self::color(color),
+			Paint::Gradient(gradient) => {
+				let spread = match gradient.extend {
+					Extend::Pad => "pad",
+					Extend::Repeat => "repeat",
+					Extend::Reflect => "reflect",
+				};
+				let mut stops = String::new();
+				for stop in gradient.stops.iter() {
+					let (color, opacity) = Self::color(stop.color.to_alpha_color::<Srgb>());
+					let _ = write!(
+						stops,
+						"<stop offset=\"{}\" stop-color=\"{color}\" stop-opacity=\"{opacity}\"/>",
+						stop.offset
+					);
+				}
+
+				let id = self.id("gradient");
+				match &gradient.kind {
+					GradientKind::Linear(pos) => {
+						let _ = write!(
+							self.defs,
+							"<linearGradient id=\"{id}\" gradientUnits=\"userSpaceOnUse\" spreadMethod=\"{spread}\" x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\"{transform}>{stops}</linearGradient>",
+							pos.start.x, pos.start.y, pos.end.x, pos.end.y
+						);
+					}
+					GradientKind::Radial(pos) => {
+						let _ = write!(
+							self.defs,
+							"<radialGradient id=\"{id}\" gradientUnits=\"userSpaceOnUse\" spreadMethod=\"{spread}\" fx=\"{}\" fy=\"{}\" fr=\"{}\" cx=\"{}\" cy=\"{}\" r=\"{}\"{transform}>{stops}</radialGradient>",
+							pos.start_center.x,
+							pos.start_center.y,
+							pos.start_radius,
+							pos.end_center.x,
+							pos.end_center.y,
+							pos.end_radius
+						);
+					}
+					// svg has no conic gradients, fall back to the first stop
+					GradientKind::Sweep(_) => {
+						return gradient
+							.stops
+							.first()
+							.map(|x| Self::color(x.color.to_alpha_color::<Srgb>()))
+							.unwrap_or_else(|| ("none".into(), 1.0));
+					}
+				}
+				(format!("url(#{id})"), 1.0)
+			}
+			Paint::Image(image) => {
+				let data = &image.image;
+				let mut rgba = data.data.data().to_vec();
+				if data.format == ImageFormat::Bgra8 {
+					for px in rgba.chunks_exact_mut(4) {
+						px.swap(0, 2);
+					}
+				}
+				if data.alpha_type == ImageAlphaType::AlphaPremultiplied {
+					for px in rgba.chunks_exact_mut(4) {
+						if px[3] != 0 {
+							for c in &mut px[..3] {
+								*c = (*c as u16 * 255 / px[3] as u16) as u8;
+							}
+						}
+					}
+				}
+				let Some(image_buf) = RgbaImage::from_raw(data.width, data.height, rgba) else {
+					return ("none".into(), 1.0);
+				};
+				let mut png = Cursor::new(Vec::new());
+				if image_buf
+					.write_to(&mut png, EncodedImageFormat::Png)
+					.is_err()
+				{
+					return ("none".into(), 1.0);
+				}
+
+				let id = self.id("image");
+				let transform = brush_transform
+					.map(|x| format!(" patternTransform=\"{}\"", Self::matrix(x)))
+					.unwrap_or_default();
+				let _ = write!(
+					self.defs,
+					"<pattern id=\"{id}\" patternUnits=\"userSpaceOnUse\" width=\"{w}\" height=\"{h}\"{transform}><image width=\"{w}\" height=\"{h}\" href=\"data:image/png;base64,{}\"/></pattern>",
+					BASE64_STANDARD.encode(png.into_inner()),
+					w = data.width,
+					h = data.height,
+				);
+				(format!("url(#{id})"), image.sampler.alpha)
+			}
+			_ => ("none".into(), 1.0),
+		}
+	}
+
+	fn fill_attrs(
+		&mut self,
+		style: Fill,
+		paint: PaintRef<'_>,
+		brush_transform: Option<Affine>,
+	) -> String {
+		let (fill, opacity) = self.paint(paint, brush_transform);
+		let rule = match style {
+			Fill::NonZero => "nonzero",
+			Fill::EvenOdd => "evenodd",
+		};
+		format!("fill=\"{fill}\" fill-opacity=\"{opacity}\" fill-rule=\"{rule}\"")
+	}
+
+	fn stroke_attrs(
+		&mut self,
+		style: &Stroke,
+		paint: PaintRef<'_>,
+		brush_transform: Option<Affine>,
+	) -> String {
+		let (stroke, opacity) = self.paint(paint, brush_transform);
+		let cap = match style.start_cap {
+			Cap::Butt => "butt",
+			Cap::Square => "square",
+			Cap::Round => "round",
+		};
+		let join = match style.join {
+			Join::Bevel => "bevel",
+			Join::Miter => "miter",
+			Join::Round => "round",
+		};
+		let mut attrs = format!(
+			"fill=\"none\" stroke=\"{stroke}\" stroke-opacity=\"{opacity}\" stroke-width=\"{}\" stroke-linecap=\"{cap}\" stroke-linejoin=\"{join}\" stroke-miterlimit=\"{}\"",
+			style.width, style.miter_limit
+		);
+		if !style.dash_pattern.is_empty() {
+			let dashes: Vec<String> = style.dash_pattern.iter().map(f64::to_string).collect();
+			let _ = write!(
+				attrs,
+				" stroke-dasharray=\"{}\" stroke-dashoffset=\"{}\"",
+				dashes.join(" "),
+				style.dash_offset
+			);
+		}
+		attrs
+	}
+
+	fn clip_path(&mut self, transform: Affine, clip: &impl Shape) -> String {
+		let id = self.id("clip");
+		let _ = write!(
+			self.defs,
+			"<clipPath id=\"{id}\"><path d=\"{}\" transform=\"{}\"/></clipPath>",
+			clip.to_path(0.1).to_svg(),
+			Self::matrix(transform)
+		);
+		id
+	}
+}
+
+impl PaintScene for SvgScenePainter {
+	fn reset(&mut self) {
+		self.defs.clear();
+		self.body.clear();
+		self.next_id = 0;
+	}
+
+	fn push_layer(
+		&mut self,
+		blend: impl Into<BlendMode>,
+		alpha: f32,
+		transform: Affine,
+		clip: &impl Shape,
+	) {
+		let clip = self.clip_path(transform, clip);
+		let blend = match blend.into().mix {
+			Mix::Multiply => "multiply",
+			Mix::Screen => "screen",
+			Mix::Overlay => "overlay",
+			Mix::Darken => "darken",
+			Mix::Lighten => "lighten",
+			Mix::ColorDodge => "color-dodge",
+			Mix::ColorBurn => "color-burn",
+			Mix::HardLight => "hard-light",
+			Mix::SoftLight => "soft-light",
+			Mix::Difference => "difference",
+			Mix::Exclusion => "exclusion",
+			Mix::Hue => "hue",
+			Mix::Saturation => "saturation",
+			Mix::Color => "color",
+			Mix::Luminosity => "luminosity",
+			_ => "normal",
+		};
+		let _ = write!(
+			self.body,
+			"<g clip-path=\"url(#{clip})\" opacity=\"{alpha}\" style=\"mix-blend-mode:{blend}\">"
+		);
+	}
+
+	fn push_clip_layer(&mut self, transform: Affine, clip: &impl Shape) {
+		let clip = self.clip_path(transform, clip);
+		let _ = write!(self.body, "<g clip-path=\"url(#{clip})\">");
+	}
+
+	fn pop_layer(&mut self) {
+		self.body.push_str("</g>");
+	}
+
+	fn stroke<'a>(
+		&mut self,
+		style: &Stroke,
+		transform: Affine,
+		paint_ref: impl Into<PaintRef<'a>>,
+		brush_transform: Option<Affine>,
+		shape: &impl Shape,
+	) {
+		let attrs = self.stroke_attrs(style, paint_ref.into(), brush_transform);
+		let _ = write!(
+			self.body,
+			"<path d=\"{}\" transform=\"{}\" {attrs}/>",
+			shape.to_path(0.1).to_svg(),
+			Self::matrix(transform)
+		);
+	}
+
+	fn fill<'a>(
+		&mut self,
+		style: Fill,
+		transform: Affine,
+		paint: impl Into<PaintRef<'a>>,
+		brush_transform: Option<Affine>,
+		shape: &impl Shape,
+	) {
+		let attrs = self.fill_attrs(style, paint.into(), brush_transform);
+		let _ = write!(
+			self.body,
+			"<path d=\"{}\" transform=\"{}\" {attrs}/>",
+			shape.to_path(0.1).to_svg(),
+			Self::matrix(transform)
+		);
+	}
+
+	fn draw_glyphs<'a, 's: 'a>(
+		&'a mut self,
+		font: &'a FontData,
+		font_size: f32,
+		_hint: bool,
+		normalized_coords: &'a [NormalizedCoord],
+		style: impl Into<StyleRef<'a>>,
+		paint: impl Into<PaintRef<'a>>,
+		brush_alpha: f32,
+		transform: Affine,
+		glyph_transform: Option<Affine>,
+		glyphs: impl Iterator<Item = anyrender::Glyph>,
+	) {
+		let path = glyph_run_path(font, font_size, normalized_coords, glyph_transform, glyphs);
+		if path.is_empty() {
+			return;
+		}
+
+		let attrs = match style.into() {
+			StyleRef::Fill(fill) => self.fill_attrs(fill, paint.into(), None),
+			StyleRef::Stroke(stroke) => self.stroke_attrs(stroke, paint.into(), None),
+		};
+		let _ = write!(
+			self.body,
+			"<path d=\"{}\" transform=\"{}\" opacity=\"{brush_alpha}\" {attrs}/>",
+			path.to_svg(),
+			Self::matrix(transform)
+		);
+	}
+
+	fn draw_box_shadow(
+		&mut self,
+		transform: Affine,
+		rect: Rect,
+		brush: Color,
+		radius: f64,
+		std_dev: f64,
+	) {
+		let id = self.id("shadow");
+		let bounds = rect.inflate(std_dev * 3.0, std_dev * 3.0);
+		let _ = write!(
+			self.defs,
+			"<filter id=\"{id}\" filterUnits=\"userSpaceOnUse\" x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"><feGaussianBlur stdDeviation=\"{std_dev}\"/></filter>",
+			bounds.x0,
+			bounds.y0,
+			bounds.width(),
+			bounds.height()
+		);
+
+		let (fill, opacity) = Self::color(brush);
+		let _ = write!(
+			self.body,
+			"<path d=\"{}\" transform=\"{}\" fill=\"{fill}\" fill-opacity=\"{opacity}\" filter=\"url(#{id})\"/>",
+			RoundedRect::from_rect(rect, radius).to_path(0.1).to_svg(),
+			Self::matrix(transform)
+		);
+	}
+}
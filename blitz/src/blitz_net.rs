@@ -1,17 +1,43 @@
+use std::{
+	collections::{HashMap, HashSet},
+	sync::{Arc, Mutex},
+};
+
 use blitz_traits::net::{
 	Body, Bytes, Entry, EntryValue, HeaderMap, NetHandler, NetProvider, Request as BlitzRequest,
-	http,
+	RequestDestination, http,
 };
 use data_url::DataUrl;
-use js_sys::{Array, Function, JsString, Object, Promise, Uint8Array};
+use js_sys::{Array, Date, Function, JsString, Object, Promise, Reflect, Uint8Array};
 use thiserror::Error;
 use wasm_bindgen::{JsCast, JsValue, prelude::wasm_bindgen};
 use wasm_bindgen_futures::{JsFuture, spawn_local};
-use web_sys::{FormData, Request, RequestInit, UrlSearchParams, console};
+use web_sys::{
+	AbortController, AbortSignal, Blob, FormData, Request, RequestInit, UrlSearchParams, console,
+};
+
+use crate::{
+	bundle::mime_for,
+	cache::{CacheLookup, HttpCache},
+	capture::BlitzArchive,
+	cookies::CookieJar,
+	document::BlitzNode,
+	files::BlitzFileStore,
+	now,
+	scheme::{BlitzSchemeRegistry, SchemeHandler, serve_native},
+};
 
 #[wasm_bindgen(typescript_custom_section)]
 const BLITZ_FETCHER_FUNCTION: &'static str = r#"
-type BlitzFetcherFunction = (req: Request) => Promise<[String, Uint8Aray]>;
+interface BlitzFetcherResponse {
+	url?: string;
+	status?: number;
+	statusText?: string;
+	headers?: Headers | Record<string, string>;
+	arrayBuffer?: () => Promise<ArrayBuffer>;
+	body?: ReadableStream<Uint8Array> | Uint8Array | null;
+}
+type BlitzFetcherFunction = (req: Request) => Promise<[String, Uint8Array] | Response | BlitzFetcherResponse>;
 "#;
 
 #[wasm_bindgen]
@@ -21,7 +47,7 @@ extern "C" {
 }
 
 #[derive(Debug, Error)]
-enum ProviderError {
+pub enum ProviderError {
 	#[error("DataUrl: {0:?}")]
 	DataUrl(data_url::DataUrlError),
 	#[error("DataUrlBase64: {0:?}")]
@@ -30,6 +56,31 @@ enum ProviderError {
 	ToStrError(http::header::ToStrError),
 	#[error("{0}")]
 	Js(String),
+	#[error("HTTP {0} {1}")]
+	Status(u16, String),
+	#[error("expected a {0:?} but got {1}")]
+	ContentType(BlitzRequestDestination, String),
+	#[error("the request was cancelled")]
+	Cancelled,
+	#[error("no file for {0}")]
+	File(String),
+	#[error("too many redirects")]
+	Redirects,
+}
+impl ProviderError {
+	pub fn kind(&self) -> BlitzNetErrorKind {
+		match self {
+			Self::DataUrl(_) => BlitzNetErrorKind::DataUrl,
+			Self::DataUrlBase64(_) => BlitzNetErrorKind::DataUrlBase64,
+			Self::ToStrError(_) => BlitzNetErrorKind::Header,
+			Self::Js(_) => BlitzNetErrorKind::Js,
+			Self::Status(..) => BlitzNetErrorKind::Status,
+			Self::ContentType(..) => BlitzNetErrorKind::ContentType,
+			Self::Cancelled => BlitzNetErrorKind::Cancelled,
+			Self::File(_) => BlitzNetErrorKind::File,
+			Self::Redirects => BlitzNetErrorKind::Redirects,
+		}
+	}
 }
 impl From<http::header::ToStrError> for ProviderError {
 	fn from(value: http::header::ToStrError) -> Self {
@@ -52,40 +103,843 @@ impl From<JsValue> for ProviderError {
 	}
 }
 
-pub struct Provider(BlitzFetcherFunction);
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlitzNetErrorKind {
+	/// The data URL was malformed.
+	DataUrl,
+	/// The data URL had invalid base64.
+	DataUrlBase64,
+	/// A request header wasn't valid text.
+	Header,
+	/// The fetcher threw or rejected.
+	Js,
+	/// The response status wasn't 2xx.
+	Status,
+	/// The response was HTML where a subresource was expected, like an error page.
+	ContentType,
+	/// The request was cancelled. These aren't reported as errors.
+	Cancelled,
+	/// A file selected in a submitted form couldn't be found.
+	File,
+	/// The response kept redirecting.
+	Redirects,
+}
+
+/// A failed request, as passed to the document's net error callback.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone)]
+pub struct BlitzNetError {
+	pub request_id: u32,
+	pub url: String,
+	pub kind: BlitzNetErrorKind,
+	pub message: String,
+}
+
+/// A request made for a node that the document handled, and that the node's `load` or `error`
+/// listeners have not been told about yet.
+#[derive(Debug, Clone)]
+pub struct NetEvent {
+	pub doc_id: usize,
+	pub node_id: usize,
+	pub destination: BlitzRequestDestination,
+	pub ok: bool,
+}
+
+/// What a request is for, as told by the part of blitz that made it.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlitzRequestDestination {
+	Document,
+	Style,
+	Image,
+	Font,
+	Other,
+}
+
+impl From<RequestDestination> for BlitzRequestDestination {
+	fn from(value: RequestDestination) -> Self {
+		match value {
+			RequestDestination::Document => Self::Document,
+			RequestDestination::Style => Self::Style,
+			RequestDestination::Font => Self::Font,
+			RequestDestination::Image => Self::Image,
+			RequestDestination::Other => Self::Other,
+		}
+	}
+}
+
+impl BlitzRequestDestination {
+	/// Lower goes first. Stylesheets and fonts block rendering while images only fill in.
+	fn priority(self) -> u8 {
+		match self {
+			Self::Document => 0,
+			Self::Style => 1,
+			Self::Font => 2,
+			Self::Image => 3,
+			Self::Other => 4,
+		}
+	}
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlitzRequestOutcome {
+	Pending,
+	Succeeded,
+	Failed,
+	/// Cancelled before it finished, so the document never saw the response.
+	Cancelled,
+}
+
+/// A request made through the provider. Times are in seconds, on the same clock as
+/// `performance.now() / 1000`.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone)]
+pub struct BlitzNetRequest {
+	pub id: u32,
+	pub doc_id: usize,
+	pub url: String,
+	pub method: String,
+	pub destination: BlitzRequestDestination,
+	/// The node the request was made for, like the `img` or the stylesheet a font came from.
+	pub node_id: Option<usize>,
+	pub start_time: f64,
+	/// When the request got a slot for its origin and went to the fetcher.
+	pub sent_time: Option<f64>,
+	pub end_time: Option<f64>,
+	/// Size of the response body, or how much of it has arrived while it's streaming.
+	pub bytes: Option<usize>,
+	/// Size of the response body from its `content-length`.
+	pub expected_bytes: Option<usize>,
+	pub outcome: BlitzRequestOutcome,
+	pub error: Option<String>,
+	/// The URL the response came from after redirects.
+	pub response_url: Option<String>,
+	pub status: Option<u16>,
+	pub status_text: Option<String>,
+	pub mime_type: Option<String>,
+	/// Whether the response came out of the HTTP cache, even if it had to be revalidated.
+	pub from_cache: bool,
+	/// Wall clock time the request started at, in milliseconds since the epoch.
+	pub started_at: f64,
+	/// The headers last sent to the fetcher, with cookies and after any redirects. Empty if
+	/// the request never reached it.
+	#[wasm_bindgen(skip)]
+	pub request_headers: Vec<(String, String)>,
+	#[wasm_bindgen(skip)]
+	pub query: Vec<(String, String)>,
+	/// Lowercased response header names and their values.
+	#[wasm_bindgen(skip)]
+	pub response_headers: Vec<(String, String)>,
+}
+
+/// A response as handed back by the fetcher.
+#[derive(Clone)]
+pub(crate) struct FetchedResponse {
+	pub(crate) url: String,
+	pub(crate) status: u16,
+	pub(crate) status_text: String,
+	/// Lowercased header names and their values.
+	pub(crate) headers: Vec<(String, String)>,
+	pub(crate) body: Bytes,
+	/// Whether the response came out of the HTTP cache.
+	pub(crate) from_cache: bool,
+}
+
+impl FetchedResponse {
+	pub(crate) fn header(&self, name: &str) -> Option<&str> {
+		self.headers
+			.iter()
+			.find(|(key, _)| key == name)
+			.map(|(_, val)| val.as_str())
+	}
+
+	pub(crate) fn mime_type(&self) -> Option<&str> {
+		self.header("content-type")
+			.map(|x| x.split(';').next().unwrap_or_default().trim())
+	}
+}
+
+/// Request and byte counts since the provider was last idle.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NetProgress {
+	pub started: u32,
+	pub finished: u32,
+	pub bytes: u64,
+	/// Requests whose size is known, either from their `content-length` or because they
+	/// finished, with how many bytes they add up to and how many of those have arrived.
+	pub sized: u32,
+	pub expected_bytes: u64,
+	pub received_bytes: u64,
+	/// Requests of unknown size that have finished.
+	pub unsized_finished: u32,
+}
+
+impl NetProgress {
+	pub fn in_flight(&self) -> u32 {
+		self.started - self.finished
+	}
+
+	/// How much of what was requested has arrived, or 1 if nothing was. Requests count by
+	/// their size, and the ones of unknown size as the average of the others.
+	pub fn fraction(&self) -> f64 {
+		if self.started == 0 {
+			return 1.0;
+		}
+		let average = if self.sized > 0 && self.expected_bytes > 0 {
+			self.expected_bytes as f64 / self.sized as f64
+		} else {
+			1.0
+		};
+		let unknown = (self.started - self.sized) as f64;
+		let total = self.expected_bytes as f64 + unknown * average;
+		if total == 0.0 {
+			// only empty responses so far
+			return self.finished as f64 / self.started as f64;
+		}
+		let done = self.received_bytes as f64 + self.unsized_finished as f64 * average;
+		done / total
+	}
+}
+
+#[derive(Default)]
+struct NetState {
+	requests: Vec<BlitzNetRequest>,
+	next_id: u32,
+	/// Id of the first request since the provider was last idle.
+	burst_start: u32,
+	in_flight: u32,
+	/// The provider went idle and listeners haven't been told yet.
+	idle_pending: bool,
+	idle_callback: Option<Function>,
+	idle_waiters: Vec<Function>,
+	error_callback: Option<Function>,
+	events: Vec<NetEvent>,
+	/// Aborts the fetch of every request in flight, by id.
+	aborts: HashMap<u32, AbortController>,
+	max_per_origin: usize,
+	/// Origins of the requests that are talking to the network, by id.
+	slots: HashMap<u32, String>,
+	queue: Vec<QueuedRequest>,
+	/// Documents whose responses are kept in `resources`.
+	keep_resources: HashSet<usize>,
+	/// Content types and bodies of successful `GET`s by document and URL, kept while the
+	/// document is alive so it can be saved with them.
+	resources: HashMap<(usize, String), (String, Bytes)>,
+	/// What the file entries of submitted forms are read from.
+	files: BlitzFileStore,
+	/// URLs of the documents requests are made for, which decide whether a request is
+	/// same-site for cookies.
+	document_urls: HashMap<usize, String>,
+}
+
+/// A request waiting for a free slot for its origin.
+struct QueuedRequest {
+	id: u32,
+	doc_id: usize,
+	url: String,
+	origin: String,
+	destination: BlitzRequestDestination,
+	/// Whether the document asked for it to go before others of the same priority, like for
+	/// images in the viewport.
+	promoted: bool,
+	/// Resolves the promise the request waits on, with whether it got a slot.
+	wake: Function,
+}
+
+/// How many finished requests are kept for the request log before the oldest are dropped.
+const MAX_FINISHED_REQUESTS: usize = 1000;
+
+/// Looks up a recorded request. They're recorded in id order and only ever removed, so they
+/// stay sorted.
+fn find_request(requests: &[BlitzNetRequest], id: u32) -> Option<&BlitzNetRequest> {
+	let index = requests.binary_search_by_key(&id, |x| x.id).ok()?;
+	Some(&requests[index])
+}
+
+fn find_request_mut(requests: &mut [BlitzNetRequest], id: u32) -> Option<&mut BlitzNetRequest> {
+	let index = requests.binary_search_by_key(&id, |x| x.id).ok()?;
+	Some(&mut requests[index])
+}
+
+impl NetState {
+	/// The URL of the document that made request `id`.
+	fn document_url(&self, id: u32) -> Option<String> {
+		let request = find_request(&self.requests, id)?;
+		self.document_urls.get(&request.doc_id).cloned()
+	}
+
+	/// Drops the oldest finished requests once there are too many, leaving the ones progress
+	/// is still counting.
+	fn prune_requests(&mut self) {
+		let finished = self
+			.requests
+			.iter()
+			.filter(|x| x.outcome != BlitzRequestOutcome::Pending)
+			.count();
+		let mut excess = finished.saturating_sub(MAX_FINISHED_REQUESTS);
+		if excess == 0 {
+			return;
+		}
+
+		let burst_start = self.burst_start;
+		self.requests.retain(|x| {
+			let drop =
+				excess > 0 && x.id < burst_start && x.outcome != BlitzRequestOutcome::Pending;
+			if drop {
+				excess -= 1;
+			}
+			!drop
+		});
+	}
+
+	fn active(&self, origin: &str) -> usize {
+		self.slots.values().filter(|x| *x == origin).count()
+	}
+
+	/// Frees the slot of a request if it has one, handing it to the most important request
+	/// queued for the same origin.
+	fn release_slot(&mut self, id: u32) {
+		if let Some(origin) = self.slots.remove(&id) {
+			self.start_queued(&origin);
+		}
+	}
+
+	/// Gives queued requests for `origin` the slots that are free, most important first.
+	fn start_queued(&mut self, origin: &str) {
+		while self.active(origin) < self.max_per_origin {
+			let Some((index, _)) = self
+				.queue
+				.iter()
+				.enumerate()
+				.filter(|(_, x)| x.origin == origin)
+				.min_by_key(|(_, x)| (x.destination.priority(), !x.promoted, x.id))
+			else {
+				return;
+			};
+
+			let next = self.queue.remove(index);
+			self.slots.insert(next.id, next.origin);
+			let _ = next.wake.call1(&JsValue::NULL, &true.into());
+		}
+	}
+}
+
+/// Holds a request's slot for its origin until dropped.
+struct SlotGuard<'a> {
+	state: &'a Mutex<NetState>,
+	id: u32,
+}
+
+impl Drop for SlotGuard<'_> {
+	fn drop(&mut self) {
+		self.state.lock().unwrap().release_slot(self.id);
+	}
+}
+
+/// Where requests go that no scheme handler takes.
+#[derive(Clone)]
+enum NetSource {
+	Fetcher(BlitzFetcherFunction),
+	/// Everything is served from a captured page and the network is never used.
+	Archive(BlitzArchive),
+}
+
+pub struct Provider {
+	source: NetSource,
+	state: Arc<Mutex<NetState>>,
+	cache: Arc<HttpCache>,
+	cookies: Arc<CookieJar>,
+	schemes: BlitzSchemeRegistry,
+}
 unsafe impl Send for Provider {}
 unsafe impl Sync for Provider {}
 
 impl Provider {
-	pub fn new(fetcher: BlitzFetcherFunction) -> Self {
-		Self(fetcher)
+	pub fn new(fetcher: BlitzFetcherFunction, schemes: BlitzSchemeRegistry) -> Self {
+		Self::with_source(NetSource::Fetcher(fetcher), schemes)
+	}
+
+	/// A provider that serves everything from `archive`.
+	pub fn from_archive(archive: BlitzArchive, schemes: BlitzSchemeRegistry) -> Self {
+		Self::with_source(NetSource::Archive(archive), schemes)
+	}
+
+	fn with_source(source: NetSource, schemes: BlitzSchemeRegistry) -> Self {
+		Self {
+			source,
+			state: Arc::new(Mutex::new(NetState {
+				max_per_origin: 6,
+				..Default::default()
+			})),
+			cache: Arc::default(),
+			cookies: Arc::default(),
+			schemes,
+		}
+	}
+
+	pub fn schemes(&self) -> &BlitzSchemeRegistry {
+		&self.schemes
+	}
+
+	pub fn files(&self) -> BlitzFileStore {
+		self.state.lock().unwrap().files.clone()
+	}
+
+	/// Reads the files of submitted forms from `files`, which should be the one the shell
+	/// provider adds picked files to.
+	pub fn set_files(&self, files: BlitzFileStore) {
+		self.state.lock().unwrap().files = files;
+	}
+
+	/// How many requests may talk to the same origin at once. The rest wait in a queue
+	/// ordered by destination.
+	pub fn max_per_origin(&self) -> usize {
+		self.state.lock().unwrap().max_per_origin
+	}
+
+	pub fn set_max_per_origin(&self, max: usize) {
+		let mut state = self.state.lock().unwrap();
+		state.max_per_origin = max.max(1);
+
+		let mut origins = state
+			.queue
+			.iter()
+			.map(|x| x.origin.clone())
+			.collect::<Vec<_>>();
+		origins.dedup();
+		for origin in origins {
+			state.start_queued(&origin);
+		}
+	}
+
+	/// URLs of the requests of `doc_id` for `destination` that are waiting for a slot.
+	pub fn queued_urls(&self, doc_id: usize, destination: BlitzRequestDestination) -> Vec<String> {
+		let state = self.state.lock().unwrap();
+		state
+			.queue
+			.iter()
+			.filter(|x| x.doc_id == doc_id && x.destination == destination)
+			.map(|x| x.url.clone())
+			.collect()
+	}
+
+	/// Moves the queued requests of `doc_id` for `url` ahead of others of the same priority.
+	pub fn promote(&self, doc_id: usize, url: &str) {
+		let mut state = self.state.lock().unwrap();
+		for request in state
+			.queue
+			.iter_mut()
+			.filter(|x| x.doc_id == doc_id && x.url == url)
+		{
+			request.promoted = true;
+		}
+	}
+
+	pub fn cache(&self) -> &HttpCache {
+		&self.cache
+	}
+
+	pub fn cookies(&self) -> &CookieJar {
+		&self.cookies
+	}
+
+	pub fn set_document_url(&self, doc_id: usize, url: String) {
+		self.state.lock().unwrap().document_urls.insert(doc_id, url);
+	}
+
+	pub fn progress(&self) -> NetProgress {
+		let state = self.state.lock().unwrap();
+		let mut progress = NetProgress::default();
+		let start = state.requests.partition_point(|x| x.id < state.burst_start);
+		for request in &state.requests[start..] {
+			progress.started += 1;
+			let finished = request.outcome != BlitzRequestOutcome::Pending;
+			let bytes = request.bytes.unwrap_or_default() as u64;
+			// finished requests are as big as what arrived
+			let expected = if finished {
+				request.bytes
+			} else {
+				request.expected_bytes
+			};
+			match expected.map(|x| x as u64) {
+				Some(expected) => {
+					progress.sized += 1;
+					progress.expected_bytes += expected;
+					progress.received_bytes += bytes.min(expected);
+				}
+				None if finished => progress.unsized_finished += 1,
+				None => {}
+			}
+			if finished {
+				progress.finished += 1;
+			}
+			progress.bytes += bytes;
+		}
+		progress
+	}
+
+	/// Every recorded request made for `doc_id`, oldest first.
+	pub fn requests(&self, doc_id: usize) -> Vec<BlitzNetRequest> {
+		let state = self.state.lock().unwrap();
+		state
+			.requests
+			.iter()
+			.filter(|x| x.doc_id == doc_id)
+			.cloned()
+			.collect()
+	}
+
+	/// Forgets requests that have finished.
+	pub fn clear_requests(&self) {
+		let mut state = self.state.lock().unwrap();
+		state
+			.requests
+			.retain(|x| x.outcome == BlitzRequestOutcome::Pending);
+	}
+
+	/// Cancels the pending requests matching `filter`, returning how many there were. Their
+	/// fetches are aborted and their responses, if any still arrive, are dropped.
+	fn cancel_where(&self, filter: impl Fn(&BlitzNetRequest) -> bool) -> u32 {
+		let controllers = {
+			let mut state = self.state.lock().unwrap();
+			let state = &mut *state;
+
+			let mut controllers = Vec::new();
+			let mut cancelled = Vec::new();
+			for request in state
+				.requests
+				.iter_mut()
+				.filter(|x| x.outcome == BlitzRequestOutcome::Pending && filter(x))
+			{
+				let Some(controller) = state.aborts.remove(&request.id) else {
+					continue;
+				};
+				if let Some(index) = state.queue.iter().position(|x| x.id == request.id) {
+					let queued = state.queue.remove(index);
+					let _ = queued.wake.call1(&JsValue::NULL, &false.into());
+				}
+				request.outcome = BlitzRequestOutcome::Cancelled;
+				request.end_time = Some(now());
+				controllers.push(controller);
+				cancelled.push(request.id);
+			}
+
+			for request in &cancelled {
+				state.release_slot(*request);
+			}
+			state.prune_requests();
+			state.in_flight -= controllers.len() as u32;
+			if !controllers.is_empty() && state.in_flight == 0 {
+				state.idle_pending = true;
+			}
+			controllers
+		};
+
+		// abort listeners run synchronously, so don't hold the lock while they do
+		for controller in &controllers {
+			controller.abort();
+		}
+		controllers.len() as u32
+	}
+
+	/// Cancels a request of `doc_id` if it's still pending, returning whether it was.
+	pub fn cancel_request(&self, doc_id: usize, id: u32) -> bool {
+		self.cancel_where(|x| x.doc_id == doc_id && x.id == id) > 0
+	}
+
+	/// Cancels every pending request of `doc_id`, returning how many there were.
+	pub fn cancel_requests(&self, doc_id: usize) -> u32 {
+		self.cancel_where(|x| x.doc_id == doc_id)
+	}
+
+	/// Keeps the bodies of what `doc_id` fetches from now on, so [`Self::resource`] can return
+	/// them. Turning it off drops the ones already kept.
+	pub fn set_keep_resources(&self, doc_id: usize, keep: bool) {
+		let mut state = self.state.lock().unwrap();
+		if keep {
+			state.keep_resources.insert(doc_id);
+		} else {
+			state.keep_resources.remove(&doc_id);
+			state.resources.retain(|(id, _), _| *id != doc_id);
+		}
+	}
+
+	/// The content type and body fetched for `url` by `doc_id`, if it was fetched successfully
+	/// while its resources were being kept.
+	pub fn resource(&self, doc_id: usize, url: &str) -> Option<(String, Bytes)> {
+		let state = self.state.lock().unwrap();
+		state.resources.get(&(doc_id, url.to_string())).cloned()
+	}
+
+	/// Drops what's kept about `doc_id`, like the bodies of its resources and the events it
+	/// hasn't taken.
+	pub fn forget_document(&self, doc_id: usize) {
+		let mut state = self.state.lock().unwrap();
+		state.resources.retain(|(id, _), _| *id != doc_id);
+		state.keep_resources.remove(&doc_id);
+		state.document_urls.remove(&doc_id);
+		state.events.retain(|x| x.doc_id != doc_id);
+		state
+			.requests
+			.retain(|x| x.doc_id != doc_id || x.outcome == BlitzRequestOutcome::Pending);
+	}
+
+	pub fn is_idle(&self) -> bool {
+		self.state.lock().unwrap().in_flight == 0
+	}
+
+	pub fn set_error_callback(&self, callback: Option<Function>) {
+		self.state.lock().unwrap().error_callback = callback;
+	}
+
+	/// Takes the requests of `doc_id` that finished since the last call.
+	pub fn take_events(&self, doc_id: usize) -> Vec<NetEvent> {
+		let mut state = self.state.lock().unwrap();
+		let (taken, rest) = std::mem::take(&mut state.events)
+			.into_iter()
+			.partition(|x| x.doc_id == doc_id);
+		state.events = rest;
+		taken
+	}
+
+	pub fn set_idle_callback(&self, callback: Option<Function>) {
+		self.state.lock().unwrap().idle_callback = callback;
+	}
+
+	/// Returns a promise resolved the next time the provider is idle, which is the next frame if
+	/// nothing is loading.
+	pub fn wait_for_idle(&self) -> Promise {
+		let mut state = self.state.lock().unwrap();
+		let promise = Promise::new(&mut |res, _| state.idle_waiters.push(res));
+		if state.in_flight == 0 {
+			state.idle_pending = true;
+		}
+		promise
+	}
+
+	/// Returns whether idle listeners are waiting to be told.
+	pub fn idle_pending(&self) -> bool {
+		self.state.lock().unwrap().idle_pending
+	}
+
+	/// Tells idle listeners if nothing is loading, from a microtask. This is called after the
+	/// document has processed responses, as those can start more requests.
+	pub fn poll_idle(&self) {
+		let (callback, waiters) = {
+			let mut state = self.state.lock().unwrap();
+			if !state.idle_pending || state.in_flight != 0 {
+				return;
+			}
+			state.idle_pending = false;
+			(
+				state.idle_callback.clone(),
+				std::mem::take(&mut state.idle_waiters),
+			)
+		};
+
+		// this runs while the document is borrowed, and listeners are likely to use it
+		spawn_local(async move {
+			for func in callback.iter().chain(&waiters) {
+				if let Err(err) = func.call0(&JsValue::NULL) {
+					console::error_2(&"network idle callback failed:".into(), &err);
+				}
+			}
+		});
+	}
+
+	fn start_request(
+		state: &Mutex<NetState>,
+		doc_id: usize,
+		request: &BlitzRequest,
+		destination: BlitzRequestDestination,
+		node_id: Option<usize>,
+	) -> (u32, Result<AbortSignal, ProviderError>) {
+		let mut state = state.lock().unwrap();
+		let id = state.next_id;
+		state.next_id += 1;
+		// progress counts from the start of every burst of loading
+		if state.in_flight == 0 {
+			state.burst_start = id;
+		}
+		state.in_flight += 1;
+
+		state.requests.push(BlitzNetRequest {
+			id,
+			doc_id,
+			url: request.url.to_string(),
+			method: request.method.to_string(),
+			destination,
+			node_id,
+			start_time: now(),
+			sent_time: None,
+			end_time: None,
+			bytes: None,
+			expected_bytes: None,
+			outcome: BlitzRequestOutcome::Pending,
+			error: None,
+			response_url: None,
+			status: None,
+			status_text: None,
+			mime_type: None,
+			from_cache: false,
+			started_at: Date::now(),
+			request_headers: Vec::new(),
+			query: request
+				.url
+				.query_pairs()
+				.map(|(key, val)| (key.into_owned(), val.into_owned()))
+				.collect(),
+			response_headers: Vec::new(),
+		});
+
+		// the request is recorded either way, so failing here fails it like any other error
+		let signal = AbortController::new().map(|controller| {
+			let signal = controller.signal();
+			state.aborts.insert(id, controller);
+			signal
+		});
+		(id, signal.map_err(ProviderError::from))
+	}
+
+	/// Records how a request ended, returning false if it was cancelled in the meantime and
+	/// the result should be ignored.
+	fn finish_request(
+		state: &Mutex<NetState>,
+		id: u32,
+		result: &Result<FetchedResponse, ProviderError>,
+	) -> bool {
+		let mut state = state.lock().unwrap();
+		let state = &mut *state;
+		// cancelling already took the request out of flight. Pending requests are never pruned,
+		// and some never got a controller, so the outcome is what tells
+		let Some(request) = find_request_mut(&mut state.requests, id)
+			.filter(|x| x.outcome == BlitzRequestOutcome::Pending)
+		else {
+			return false;
+		};
+		state.aborts.remove(&id);
+		state.in_flight -= 1;
+		if state.in_flight == 0 {
+			state.idle_pending = true;
+		}
+
+		request.end_time = Some(now());
+		match result {
+			Ok(response) => {
+				request.response_url = Some(response.url.clone());
+				request.status = Some(response.status);
+				request.status_text = Some(response.status_text.clone());
+				request.mime_type = response.mime_type().map(ToOwned::to_owned);
+				request.from_cache = response.from_cache;
+				request.response_headers = response.headers.clone();
+				request.bytes = Some(response.body.len());
+				request.outcome = BlitzRequestOutcome::Succeeded;
+
+				// documents refer to resources by the URL they asked for, not where it redirected
+				let (doc_id, url) = (request.doc_id, request.url.clone());
+				if request.method == "GET" && state.keep_resources.contains(&doc_id) {
+					let mime = response
+						.mime_type()
+						.unwrap_or_else(|| {
+							mime_for(response.url.split(['?', '#']).next().unwrap_or_default())
+						})
+						.to_string();
+					state
+						.resources
+						.insert((doc_id, url), (mime, response.body.clone()));
+				}
+			}
+			Err(err) => {
+				if let ProviderError::Status(status, text) = err {
+					request.status = Some(*status);
+					request.status_text = Some(text.clone());
+				}
+				request.error = Some(err.to_string());
+				request.outcome = BlitzRequestOutcome::Failed;
+			}
+		}
+		state.prune_requests();
+		true
+	}
+
+	/// Queues `load` or `error` for the node a request was made for, once its handler has been
+	/// given the response.
+	fn queue_event(state: &Mutex<NetState>, event: Option<NetEvent>) {
+		if let Some(event) = event {
+			state.lock().unwrap().events.push(event);
+		}
+	}
+
+	fn report_error(state: &Mutex<NetState>, error: BlitzNetError, node_id: Option<usize>) {
+		let callback = state.lock().unwrap().error_callback.clone();
+		let Some(callback) = callback else {
+			console::warn_2(&"fetch failed:".into(), &error.message.into());
+			return;
+		};
+
+		let node = node_id.map_or(JsValue::UNDEFINED, |x| BlitzNode(x).into());
+		if let Err(err) = callback.call2(&JsValue::NULL, &error.into(), &node) {
+			console::error_2(&"net error callback failed:".into(), &err);
+		}
 	}
 }
 
 impl Provider {
-	fn get_headers(map: HeaderMap, content_ty: &str) -> Result<JsValue, ProviderError> {
-		let array = Array::new();
+	/// The request headers as they are sent to the fetcher.
+	fn header_list(
+		map: &HeaderMap,
+		content_ty: &str,
+	) -> Result<Vec<(String, String)>, ProviderError> {
+		let mut headers = Vec::new();
 
 		for key in map.keys() {
-			let key_js = key.to_string().into();
 			for val in map.get_all(key) {
-				array.push(&Array::of2(&key_js, &val.to_str()?.into()));
+				headers.push((key.to_string(), val.to_str()?.to_string()));
 			}
 		}
-		array.push(&Array::of2(&"Content-Type".into(), &content_ty.into()).into());
+		headers.push(("Content-Type".to_string(), content_ty.to_string()));
+
+		Ok(headers)
+	}
+
+	fn get_headers(headers: &[(String, String)]) -> Result<JsValue, ProviderError> {
+		let array = Array::new();
+		for (key, val) in headers {
+			// browsers drop Cookie from requests made by scripts, so the fetcher has to put it
+			// back from another name
+			let key = if key.eq_ignore_ascii_case("cookie") {
+				"x-blitz-cookie"
+			} else {
+				key
+			};
+			array.push(&Array::of2(&key.into(), &val.into()));
+		}
 
 		Ok(Object::from_entries(&array.into())?.into())
 	}
 
-	fn get_body(body: Body, formdata: bool) -> Result<JsValue, ProviderError> {
+	async fn get_body(
+		body: Body,
+		formdata: bool,
+		files: &BlitzFileStore,
+	) -> Result<JsValue, ProviderError> {
 		Ok(match body {
 			Body::Form(mut form) if formdata => {
 				let js = FormData::new()?;
 				for Entry { name, value } in form.0.drain(..) {
 					match value {
 						EntryValue::String(value) => js.set_with_str(&name, &value)?,
-						_ => {
-							console::warn_1(&"invalid formdata type, skipping".into());
+						// inputs can select several files under the same name
+						EntryValue::File(path) => {
+							let (blob, filename) = files.read(&path).await?;
+							js.append_with_blob_and_filename(&name, &blob, &filename)?;
+						}
+						// a file input with nothing selected still submits an empty file
+						EntryValue::EmptyFile => {
+							let blob = Blob::new()?;
+							js.append_with_blob_and_filename(&name, &blob, "")?;
 						}
 					}
 				}
@@ -96,9 +950,15 @@ impl Provider {
 				for Entry { name, value } in form.0.drain(..) {
 					match value {
 						EntryValue::String(value) => js.set(&name, &value),
-						_ => {
-							console::warn_1(&"invalid formdata type, skipping".into());
-						}
+						// urlencoded forms only send the names of files
+						EntryValue::File(path) => js.append(
+							&name,
+							&path
+								.file_name()
+								.map(|x| x.to_string_lossy())
+								.unwrap_or_default(),
+						),
+						EntryValue::EmptyFile => js.append(&name, ""),
 					}
 				}
 				js.into()
@@ -108,51 +968,633 @@ impl Provider {
 		})
 	}
 
-	async fn fetch_inner(
+	fn read_headers(headers: &JsValue) -> Result<Vec<(String, String)>, ProviderError> {
+		if headers.is_undefined() || headers.is_null() {
+			return Ok(Vec::new());
+		}
+
+		// Headers and Maps iterate as [name, value] pairs, plain objects don't iterate at all
+		let entries = match js_sys::try_iter(headers)? {
+			Some(iter) => iter.collect::<Result<Vec<_>, _>>()?,
+			None => Object::entries(headers.unchecked_ref()).to_vec(),
+		};
+
+		Ok(entries
+			.into_iter()
+			.filter_map(|entry| {
+				let entry: Array = entry.unchecked_into();
+				Some((
+					entry.at(0).as_string()?.to_ascii_lowercase(),
+					entry.at(1).as_string()?,
+				))
+			})
+			.collect())
+	}
+
+	/// Reads a `ReadableStream<Uint8Array>` a chunk at a time, recording how much has arrived
+	/// so far. Only one read is outstanding at once, so the stream never buffers more than its
+	/// high water mark ahead of us.
+	async fn read_stream(
+		stream: &JsValue,
+		state: &Mutex<NetState>,
+		id: u32,
+		expected: Option<usize>,
+	) -> Result<Vec<u8>, ProviderError> {
+		// don't trust a huge content-length enough to allocate it all up front
+		const MAX_PREALLOCATE: usize = 64 * 1024 * 1024;
+
+		let reader = Reflect::get(stream, &"getReader".into())?
+			.unchecked_into::<Function>()
+			.call0(stream)?;
+		let read: Function = Reflect::get(&reader, &"read".into())?.unchecked_into();
+
+		let mut body = Vec::with_capacity(expected.unwrap_or_default().min(MAX_PREALLOCATE));
+		loop {
+			let promise: Promise = read.call0(&reader)?.unchecked_into();
+			let chunk = JsFuture::from(promise).await?;
+			if Reflect::get(&chunk, &"done".into())?.is_truthy() {
+				break;
+			}
+
+			let value: Uint8Array = Reflect::get(&chunk, &"value".into())?.unchecked_into();
+			let start = body.len();
+			body.resize(start + value.length() as usize, 0);
+			value.copy_to(&mut body[start..]);
+
+			let mut state = state.lock().unwrap();
+			match find_request_mut(&mut state.requests, id) {
+				Some(request) if request.outcome == BlitzRequestOutcome::Cancelled => {
+					// in case the fetcher ignored the abort signal
+					drop(state);
+					let cancel: Function =
+						Reflect::get(&reader, &"cancel".into())?.unchecked_into();
+					let _ = cancel.call0(&reader);
+					return Err(ProviderError::Cancelled);
+				}
+				Some(request) => request.bytes = Some(body.len()),
+				None => {}
+			}
+		}
+
+		Ok(body)
+	}
+
+	/// Accepts either the old `[url, Uint8Array]` tuple or anything shaped like a `Response`,
+	/// whose body may be a `ReadableStream`.
+	async fn read_response(
+		value: JsValue,
+		request_url: String,
+		state: &Mutex<NetState>,
+		id: u32,
+		destination: BlitzRequestDestination,
+	) -> Result<FetchedResponse, ProviderError> {
+		if Array::is_array(&value) {
+			let res: Array = value.unchecked_into();
+			let url: JsString = res.at(0).unchecked_into();
+			let bytes: Uint8Array = res.at(1).unchecked_into();
+
+			return Ok(FetchedResponse {
+				url: url.into(),
+				status: 200,
+				status_text: String::new(),
+				headers: Vec::new(),
+				body: bytes.to_vec().into(),
+				from_cache: false,
+			});
+		}
+
+		let get = |key: &str| Reflect::get(&value, &key.into());
+
+		let headers = Self::read_headers(&get("headers")?)?;
+		let expected = headers
+			.iter()
+			.find(|(key, _)| key == "content-length")
+			.and_then(|(_, val)| val.trim().parse::<usize>().ok());
+		{
+			let mut state = state.lock().unwrap();
+			if let Some(request) = find_request_mut(&mut state.requests, id) {
+				request.expected_bytes = expected;
+			}
+		}
+
+		let url = get("url")?
+			.as_string()
+			.filter(|x| !x.is_empty())
+			.unwrap_or(request_url);
+		let status = get("status")?.as_f64().map_or(200, |x| x as u16);
+		let status_text = get("statusText")?.as_string().unwrap_or_default();
+
+		let body = get("body")?;
+		let has_stream = !body.is_undefined()
+			&& !body.is_null()
+			&& Reflect::get(&body, &"getReader".into())?.is_function();
+		let body = if Self::check_head(status, &status_text, &headers, destination).is_err() {
+			// error pages, redirects and 304s are never read, so don't download them
+			if has_stream {
+				let cancel: Function = Reflect::get(&body, &"cancel".into())?.unchecked_into();
+				let _ = cancel.call0(&body);
+			}
+			Vec::new()
+		} else if body.is_undefined() || body.is_null() {
+			// a Response with no body still has an arrayBuffer, which is just empty
+			Vec::new()
+		} else if has_stream {
+			Self::read_stream(&body, state, id, expected).await?
+		} else if body.is_instance_of::<Uint8Array>() {
+			Uint8Array::new(&body).to_vec()
+		} else if let Some(func) = get("arrayBuffer")?.dyn_ref::<Function>() {
+			let promise: Promise = func.call0(&value)?.unchecked_into();
+			Uint8Array::new(&JsFuture::from(promise).await?).to_vec()
+		} else {
+			Uint8Array::new(&body).to_vec()
+		};
+
+		Ok(FetchedResponse {
+			url,
+			status,
+			status_text,
+			headers,
+			body: body.into(),
+			from_cache: false,
+		})
+	}
+
+	/// Rejects error statuses, and HTML where a subresource was expected, which is almost
+	/// always an error page served with the wrong status.
+	fn check_response(
+		response: &FetchedResponse,
+		destination: BlitzRequestDestination,
+	) -> Result<(), ProviderError> {
+		Self::check_head(
+			response.status,
+			&response.status_text,
+			&response.headers,
+			destination,
+		)
+	}
+
+	/// [`Self::check_response`] for a response whose body hasn't been read yet.
+	fn check_head(
+		status: u16,
+		status_text: &str,
+		headers: &[(String, String)],
+		destination: BlitzRequestDestination,
+	) -> Result<(), ProviderError> {
+		if !(200..300).contains(&status) {
+			return Err(ProviderError::Status(status, status_text.to_string()));
+		}
+
+		let mime = headers
+			.iter()
+			.find(|(key, _)| key == "content-type")
+			.map(|(_, val)| val.split(';').next().unwrap_or_default().trim());
+		if let Some(mime) = mime
+			&& mime.eq_ignore_ascii_case("text/html")
+			&& !matches!(
+				destination,
+				BlitzRequestDestination::Document | BlitzRequestDestination::Other
+			) {
+			return Err(ProviderError::ContentType(destination, mime.to_string()));
+		}
+
+		Ok(())
+	}
+
+	/// Fetches through the HTTP cache: fresh responses are served from it, stale ones are
+	/// revalidated, and concurrent requests for the same resource wait for the first one.
+	#[allow(clippy::too_many_arguments)]
+	async fn fetch_cached(
 		fetcher: BlitzFetcherFunction,
 		request: BlitzRequest,
-	) -> Result<(String, Bytes), ProviderError> {
-		Ok(match request.url.scheme() {
-			"data" => {
-				let data_url = DataUrl::process(request.url.as_str())?;
-				let decoded = data_url.decode_to_vec()?;
-				(request.url.to_string(), Bytes::from(decoded.0))
+		state: &Mutex<NetState>,
+		cache: &HttpCache,
+		cookies: &CookieJar,
+		id: u32,
+		signal: AbortSignal,
+		destination: BlitzRequestDestination,
+	) -> Result<FetchedResponse, ProviderError> {
+		let mut headers = Self::header_list(&request.headers, &request.content_type)?;
+		// added before the cache sees the headers, for responses that vary on it
+		let site = state.lock().unwrap().document_url(id);
+		if let Some(cookie) = cookies.header(request.url.as_str(), site.as_deref()) {
+			headers.push(("cookie".to_string(), cookie));
+		}
+
+		let Some(key) = HttpCache::key(request.method.as_str(), request.url.as_str()) else {
+			return Self::fetch_redirected(
+				fetcher,
+				request,
+				headers,
+				state,
+				cookies,
+				site.as_deref(),
+				id,
+				signal,
+				destination,
+			)
+			.await;
+		};
+
+		let mut lookup = cache.lookup(&key, &headers).await;
+		let mut _guard = None;
+		if let CacheLookup::Miss = lookup {
+			// if another request was already fetching this, its response is likely cached now
+			match cache.join(&key).await {
+				Some(guard) => _guard = Some(guard),
+				None => lookup = cache.lookup(&key, &headers).await,
+			}
+		}
+
+		let validators = match lookup {
+			CacheLookup::Fresh(response) => return Ok(response),
+			CacheLookup::Stale(validators) => validators,
+			CacheLookup::Miss => Vec::new(),
+		};
+		let request_headers = headers.clone();
+		let revalidating = !validators.is_empty();
+		headers.extend(validators);
+
+		let retry = revalidating.then(|| (fetcher.clone(), request.clone(), signal.clone()));
+		let response = match Self::fetch_redirected(
+			fetcher,
+			request,
+			headers,
+			state,
+			cookies,
+			site.as_deref(),
+			id,
+			signal,
+			destination,
+		)
+		.await
+		{
+			Ok(response) => response,
+			// a stale response may stand in if the server can't be reached
+			Err(err @ ProviderError::Js(_)) if revalidating => {
+				return cache.stale(&key, &request_headers).ok_or(err);
+			}
+			Err(err) => return Err(err),
+		};
+		let response = match retry {
+			Some((fetcher, request, signal)) if response.status == 304 => {
+				if let Some(response) = cache.revalidated(&key, &request_headers, &response) {
+					return Ok(response);
+				}
+				// what was revalidated got evicted in the meantime, so ask for all of it again
+				Self::fetch_redirected(
+					fetcher,
+					request,
+					request_headers.clone(),
+					state,
+					cookies,
+					site.as_deref(),
+					id,
+					signal,
+					destination,
+				)
+				.await?
+			}
+			_ => response,
+		};
+
+		cache.store(&key, &request_headers, &response);
+		Ok(response)
+	}
+
+	/// Fetches from the network, following redirects here rather than in the fetcher so every
+	/// hop stores the cookies it set and sends the ones for where it goes next. Fetchers that
+	/// follow redirects themselves still work, their cookies just skip the jar until the end.
+	#[allow(clippy::too_many_arguments)]
+	async fn fetch_redirected(
+		fetcher: BlitzFetcherFunction,
+		mut request: BlitzRequest,
+		mut headers: Vec<(String, String)>,
+		state: &Mutex<NetState>,
+		cookies: &CookieJar,
+		site: Option<&str>,
+		id: u32,
+		signal: AbortSignal,
+		destination: BlitzRequestDestination,
+	) -> Result<FetchedResponse, ProviderError> {
+		const MAX_REDIRECTS: usize = 20;
+
+		for _ in 0..=MAX_REDIRECTS {
+			let response = Self::fetch_network(
+				fetcher.clone(),
+				request.clone(),
+				headers,
+				state,
+				id,
+				signal.clone(),
+				destination,
+			)
+			.await?;
+			Self::store_cookies(cookies, &response, site);
+
+			let next = matches!(response.status, 301 | 302 | 303 | 307 | 308)
+				.then(|| response.header("location"))
+				.flatten()
+				.and_then(|x| request.url.join(x).ok());
+			let Some(next) = next else {
+				return Ok(response);
+			};
+
+			// like browsers, a 303 and a POST that got a 301 or 302 go on as a GET without a body
+			let status = response.status;
+			if (status == 303 && request.method != http::Method::HEAD)
+				|| (matches!(status, 301 | 302) && request.method == http::Method::POST)
+			{
+				request.method = http::Method::GET;
+				request.body = Body::Empty;
+				request.content_type = String::new();
 			}
-			_ => {
-				let func = fetcher.unchecked_into::<Function>();
-				let init = RequestInit::new();
-				init.set_method(&request.method.to_string());
-				init.set_headers(&Self::get_headers(request.headers, &request.content_type)?);
-				init.set_body(&Self::get_body(
-					request.body,
-					request.content_type == "multipart/form-data",
-				)?);
+			request.url = next;
 
-				let req = Request::new_with_str_and_init(&request.url.to_string(), &init)?;
+			headers = Self::header_list(&request.headers, &request.content_type)?;
+			if let Some(cookie) = cookies.header(request.url.as_str(), site) {
+				headers.push(("cookie".to_string(), cookie));
+			}
+		}
 
-				let promise: Promise = func.call1(&JsValue::NULL, &req.into())?.unchecked_into();
-				let res: Array = JsFuture::from(promise).await?.unchecked_into();
-				let url: JsString = res.at(0).unchecked_into();
-				let bytes: Uint8Array = res.at(1).unchecked_into();
+		Err(ProviderError::Redirects)
+	}
 
-				(url.into(), bytes.to_vec().into())
+	/// Stores the cookies a response from the network set. `Set-Cookie` can't be read from a
+	/// `Response`, so the fetcher may pass them as `x-blitz-set-cookie`, URI encoded and comma
+	/// separated.
+	fn store_cookies(cookies: &CookieJar, response: &FetchedResponse, site: Option<&str>) {
+		let mut set = Vec::new();
+		for (key, val) in &response.headers {
+			match key.as_str() {
+				"set-cookie" => set.push(val.clone()),
+				"x-blitz-set-cookie" => set.extend(val.split(',').filter_map(|x| {
+					js_sys::decode_uri_component(x.trim())
+						.ok()
+						.map(String::from)
+				})),
+				_ => {}
 			}
-		})
+		}
+		if !set.is_empty() {
+			cookies.set_from_response(&response.url, site, set.iter().map(String::as_str));
+		}
+	}
+
+	/// Waits until the request may talk to its origin, returning a guard that frees the slot
+	/// again.
+	async fn wait_for_slot<'a>(
+		state: &'a Mutex<NetState>,
+		id: u32,
+		url: &str,
+		origin: String,
+	) -> Result<SlotGuard<'a>, ProviderError> {
+		let promise = {
+			let mut state = state.lock().unwrap();
+			let state = &mut *state;
+			let Some(request) = find_request(&state.requests, id) else {
+				return Err(ProviderError::Cancelled);
+			};
+			let (doc_id, destination) = (request.doc_id, request.destination);
+
+			if state.active(&origin) < state.max_per_origin {
+				state.slots.insert(id, origin);
+				None
+			} else {
+				let mut wake = None;
+				let promise = Promise::new(&mut |res, _| wake = Some(res));
+				state.queue.push(QueuedRequest {
+					id,
+					doc_id,
+					url: url.to_string(),
+					origin,
+					destination,
+					promoted: false,
+					wake: wake.unwrap(),
+				});
+				Some(promise)
+			}
+		};
+
+		if let Some(promise) = promise
+			&& !JsFuture::from(promise).await?.is_truthy()
+		{
+			return Err(ProviderError::Cancelled);
+		}
+
+		let mut locked = state.lock().unwrap();
+		if let Some(request) = find_request_mut(&mut locked.requests, id) {
+			request.sent_time = Some(now());
+		}
+		Ok(SlotGuard { state, id })
+	}
+
+	#[allow(clippy::too_many_arguments)]
+	async fn fetch_network(
+		fetcher: BlitzFetcherFunction,
+		request: BlitzRequest,
+		headers: Vec<(String, String)>,
+		state: &Mutex<NetState>,
+		id: u32,
+		signal: AbortSignal,
+		destination: BlitzRequestDestination,
+	) -> Result<FetchedResponse, ProviderError> {
+		let _slot = Self::wait_for_slot(
+			state,
+			id,
+			request.url.as_str(),
+			request.url.origin().ascii_serialization(),
+		)
+		.await?;
+
+		Self::call_fetcher(
+			&fetcher.unchecked_into(),
+			request,
+			headers,
+			state,
+			id,
+			signal,
+			destination,
+		)
+		.await
+	}
+
+	/// Hands a request to a JS function shaped like the fetcher and reads what it returns.
+	#[allow(clippy::too_many_arguments)]
+	async fn call_fetcher(
+		func: &Function,
+		request: BlitzRequest,
+		headers: Vec<(String, String)>,
+		state: &Mutex<NetState>,
+		id: u32,
+		signal: AbortSignal,
+		destination: BlitzRequestDestination,
+	) -> Result<FetchedResponse, ProviderError> {
+		let init = RequestInit::new();
+		init.set_method(&request.method.to_string());
+		init.set_signal(Some(&signal));
+		init.set_headers(&Self::get_headers(&headers)?);
+		let files = {
+			let mut state = state.lock().unwrap();
+			if let Some(record) = find_request_mut(&mut state.requests, id) {
+				record.request_headers = headers.clone();
+			}
+			state.files.clone()
+		};
+		init.set_body(
+			&Self::get_body(
+				request.body,
+				request.content_type == "multipart/form-data",
+				&files,
+			)
+			.await?,
+		);
+
+		let req = Request::new_with_str_and_init(&request.url.to_string(), &init)?;
+
+		let promise: Promise = func.call1(&JsValue::NULL, &req.into())?.unchecked_into();
+		Self::read_response(
+			JsFuture::from(promise).await?,
+			request.url.to_string(),
+			state,
+			id,
+			destination,
+		)
+		.await
+	}
+
+	/// Answers a request for a custom scheme with its registered handler.
+	async fn fetch_scheme(
+		scheme: SchemeHandler,
+		request: BlitzRequest,
+		state: &Mutex<NetState>,
+		id: u32,
+		signal: AbortSignal,
+		destination: BlitzRequestDestination,
+	) -> Result<FetchedResponse, ProviderError> {
+		match scheme {
+			SchemeHandler::Js(func) => {
+				let headers = Self::header_list(&request.headers, &request.content_type)?;
+				Self::call_fetcher(&func, request, headers, state, id, signal, destination).await
+			}
+			SchemeHandler::Bundle(bundle) => {
+				Ok(bundle.serve(request.url.as_str(), request.url.path()))
+			}
+			SchemeHandler::Native(func) => Ok(serve_native(&*func, request.url.as_str())),
+		}
+	}
+
+	#[allow(clippy::too_many_arguments)]
+	async fn fetch_inner(
+		source: NetSource,
+		request: BlitzRequest,
+		state: &Mutex<NetState>,
+		cache: &HttpCache,
+		cookies: &CookieJar,
+		schemes: &BlitzSchemeRegistry,
+		id: u32,
+		signal: AbortSignal,
+		destination: BlitzRequestDestination,
+	) -> Result<FetchedResponse, ProviderError> {
+		let response = match request.url.scheme() {
+			"data" => {
+				let data_url = DataUrl::process(request.url.as_str())?;
+				let decoded = data_url.decode_to_vec()?;
+				FetchedResponse {
+					url: request.url.to_string(),
+					status: 200,
+					status_text: "OK".to_string(),
+					headers: vec![("content-type".to_string(), data_url.mime_type().to_string())],
+					body: Bytes::from(decoded.0),
+					from_cache: false,
+				}
+			}
+			scheme => match (schemes.get(scheme), source) {
+				(Some(scheme), _) => {
+					Self::fetch_scheme(scheme, request, state, id, signal, destination).await?
+				}
+				(None, NetSource::Archive(archive)) => archive.serve(request.url.as_str()),
+				(None, NetSource::Fetcher(fetcher)) => {
+					Self::fetch_cached(
+						fetcher,
+						request,
+						state,
+						cache,
+						cookies,
+						id,
+						signal,
+						destination,
+					)
+					.await?
+				}
+			},
+		};
+
+		Self::check_response(&response, destination)?;
+		Ok(response)
 	}
 }
 
 impl NetProvider for Provider {
-	fn fetch(&self, _doc_id: usize, request: BlitzRequest, handler: Box<dyn NetHandler>) {
-		let func = self.0.clone();
+	fn fetch(&self, doc_id: usize, request: BlitzRequest, handler: Box<dyn NetHandler>) {
+		let source = self.source.clone();
+		let state = self.state.clone();
+		let cache = self.cache.clone();
+		let cookies = self.cookies.clone();
+		let schemes = self.schemes.clone();
+		let destination = handler.destination().into();
+		let node_id = handler.node_id();
+		let (id, signal) = Self::start_request(&state, doc_id, &request, destination, node_id);
+		let url = request.url.to_string();
+		let event = move |ok| {
+			node_id.map(|node_id| NetEvent {
+				doc_id,
+				node_id,
+				destination,
+				ok,
+			})
+		};
+
 		spawn_local(async move {
-			let result = Self::fetch_inner(func.into(), request).await;
+			let result = match signal {
+				Ok(signal) => {
+					Self::fetch_inner(
+						source,
+						request,
+						&state,
+						&cache,
+						&cookies,
+						&schemes,
+						id,
+						signal,
+						destination,
+					)
+					.await
+				}
+				Err(err) => Err(err),
+			};
+
+			if !Self::finish_request(&state, id, &result) {
+				return;
+			}
 
 			match result {
-				Ok((response_url, bytes)) => {
-					handler.bytes(response_url, bytes);
+				Ok(response) => {
+					handler.bytes(response.url, response.body);
+					Self::queue_event(&state, event(true));
 				}
 				Err(x) => {
-					console::warn_2(&"fetch failed:".into(), &x.to_string().into());
+					handler.error(url.clone(), x.to_string());
+					Self::queue_event(&state, event(false));
+					Self::report_error(
+						&state,
+						BlitzNetError {
+							request_id: id,
+							url,
+							kind: x.kind(),
+							message: x.to_string(),
+						},
+						node_id,
+					);
 				}
 			};
 		});
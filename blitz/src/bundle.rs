@@ -0,0 +1,264 @@
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::{Context, Result, bail};
+use blitz_traits::net::Bytes;
+use miniz_oxide::inflate::decompress_to_vec_with_limit;
+use wasm_bindgen::{JsError, prelude::wasm_bindgen};
+
+use crate::{anyhow_to_obj, blitz_net::FetchedResponse};
+
+/// Guesses a content type from a file name, for archives that don't record one.
+pub fn mime_for(path: &str) -> &'static str {
+	let ext = path.rsplit_once('.').map(|x| x.1).unwrap_or_default();
+	match ext.to_ascii_lowercase().as_str() {
+		"html" | "htm" => "text/html",
+		"xhtml" => "application/xhtml+xml",
+		"css" => "text/css",
+		"js" | "mjs" => "text/javascript",
+		"json" => "application/json",
+		"txt" => "text/plain",
+		"xml" => "application/xml",
+		"svg" => "image/svg+xml",
+		"png" => "image/png",
+		"jpg" | "jpeg" => "image/jpeg",
+		"gif" => "image/gif",
+		"webp" => "image/webp",
+		"avif" => "image/avif",
+		"ico" => "image/x-icon",
+		"bmp" => "image/bmp",
+		"woff" => "font/woff",
+		"woff2" => "font/woff2",
+		"ttf" => "font/ttf",
+		"otf" => "font/otf",
+		"wasm" => "application/wasm",
+		_ => "application/octet-stream",
+	}
+}
+
+fn normalize(path: &str) -> String {
+	path.trim_start_matches("./")
+		.trim_start_matches('/')
+		.to_string()
+}
+
+fn read_u16(data: &[u8], at: usize) -> Result<u16> {
+	Ok(u16::from_le_bytes(
+		data.get(at..at + 2).context("truncated zip")?.try_into()?,
+	))
+}
+
+fn read_u32(data: &[u8], at: usize) -> Result<u32> {
+	Ok(u32::from_le_bytes(
+		data.get(at..at + 4).context("truncated zip")?.try_into()?,
+	))
+}
+
+/// Reads a NUL terminated field of a tar header.
+fn tar_str(field: &[u8]) -> String {
+	let end = field.iter().position(|x| *x == 0).unwrap_or(field.len());
+	String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+fn parse_tar(data: &[u8]) -> Result<HashMap<String, Bytes>> {
+	let mut files = HashMap::new();
+	let mut offset = 0;
+	// set by pax and gnu headers for the entry after them
+	let mut long_name = None;
+
+	while offset + 512 <= data.len() {
+		let header = &data[offset..offset + 512];
+		if header.iter().all(|x| *x == 0) {
+			break;
+		}
+
+		let size = tar_str(&header[124..136]);
+		let size = usize::from_str_radix(size.trim(), 8)
+			.with_context(|| format!("invalid tar entry size {size:?}"))?;
+		let start = offset + 512;
+		let contents = data
+			.get(start..start + size)
+			.context("truncated tar entry")?;
+		offset = start + size.div_ceil(512) * 512;
+
+		match header[156] {
+			b'0' | 0 => {
+				let name = long_name.take().unwrap_or_else(|| {
+					let prefix = tar_str(&header[345..500]);
+					let name = tar_str(&header[0..100]);
+					if prefix.is_empty() {
+						name
+					} else {
+						format!("{prefix}/{name}")
+					}
+				});
+				files.insert(normalize(&name), Bytes::copy_from_slice(contents));
+			}
+			b'L' => long_name = Some(tar_str(contents)),
+			b'x' => {
+				// records are "<len> <key>=<value>\n"
+				let records = String::from_utf8_lossy(contents);
+				long_name = records
+					.lines()
+					.filter_map(|x| x.split_once(' ')?.1.strip_prefix("path="))
+					.map(ToOwned::to_owned)
+					.last()
+					.or(long_name);
+			}
+			_ => long_name = None,
+		}
+	}
+
+	Ok(files)
+}
+
+fn parse_zip(data: &[u8]) -> Result<HashMap<String, Bytes>> {
+	const EOCD: u32 = 0x06054b50;
+	const CENTRAL: u32 = 0x02014b50;
+	const LOCAL: u32 = 0x04034b50;
+
+	// the end of central directory record is followed by a comment of up to 64k
+	let search_start = data.len().saturating_sub(22 + u16::MAX as usize);
+	let eocd = (search_start..data.len().saturating_sub(21))
+		.rev()
+		.find(|x| read_u32(data, *x).is_ok_and(|x| x == EOCD))
+		.context("not a zip file")?;
+
+	let count = read_u16(data, eocd + 10)?;
+	let mut offset = read_u32(data, eocd + 16)? as usize;
+	if offset == u32::MAX as usize {
+		bail!("zip64 archives are not supported");
+	}
+
+	let mut files = HashMap::new();
+	for _ in 0..count {
+		if read_u32(data, offset)? != CENTRAL {
+			bail!("invalid zip central directory");
+		}
+		let flags = read_u16(data, offset + 8)?;
+		let method = read_u16(data, offset + 10)?;
+		let compressed = read_u32(data, offset + 20)? as usize;
+		let uncompressed = read_u32(data, offset + 24)? as usize;
+		let name_len = read_u16(data, offset + 28)? as usize;
+		let extra_len = read_u16(data, offset + 30)? as usize;
+		let comment_len = read_u16(data, offset + 32)? as usize;
+		let local = read_u32(data, offset + 42)? as usize;
+		let name = data
+			.get(offset + 46..offset + 46 + name_len)
+			.context("truncated zip")?;
+		let name = String::from_utf8_lossy(name).into_owned();
+		offset += 46 + name_len + extra_len + comment_len;
+
+		if name.ends_with('/') {
+			continue;
+		}
+		if flags & 1 != 0 {
+			bail!("{name} is encrypted");
+		}
+
+		if read_u32(data, local)? != LOCAL {
+			bail!("invalid zip local header for {name}");
+		}
+		let start = local
+			+ 30
+			+ read_u16(data, local + 26)? as usize
+			+ read_u16(data, local + 28)? as usize;
+		// the header was read, so only the size can overflow
+		let contents = start
+			.checked_add(compressed)
+			.and_then(|end| data.get(start..end))
+			.context("truncated zip")?;
+
+		let contents = match method {
+			0 => contents.to_vec(),
+			8 => decompress_to_vec_with_limit(contents, uncompressed)
+				.map_err(|err| anyhow::anyhow!("failed to inflate {name}: {err:?}"))?,
+			_ => bail!("{name} uses unsupported compression method {method}"),
+		};
+		files.insert(normalize(&name), contents.into());
+	}
+
+	Ok(files)
+}
+
+/// Files from a tar or zip archive kept in memory, to serve through a custom scheme with
+/// [`BlitzSchemeRegistry::register_bundle`](crate::scheme::BlitzSchemeRegistry::register_bundle).
+///
+/// A URL's path is looked up in the bundle and its host is ignored, so `app:///index.html` and
+/// `app://anything/index.html` are the same file. Paths ending in `/` serve their
+/// `index.html`.
+#[wasm_bindgen]
+#[derive(Clone)]
+pub struct BlitzBundle {
+	files: Arc<HashMap<String, Bytes>>,
+}
+
+impl BlitzBundle {
+	pub fn file(&self, path: &str) -> Option<&Bytes> {
+		let path = normalize(path);
+		match self.files.get(&path) {
+			Some(file) => Some(file),
+			None if path.is_empty() || path.ends_with('/') => {
+				self.files.get(&format!("{path}index.html"))
+			}
+			None => None,
+		}
+	}
+
+	/// Answers a request for `url` from the bundle, with a 404 if the file isn't in it.
+	pub(crate) fn serve(&self, url: &str, path: &str) -> FetchedResponse {
+		let path = js_sys::decode_uri_component(path)
+			.map(String::from)
+			.unwrap_or_else(|_| path.to_string());
+		let (status, status_text, body) = match self.file(&path) {
+			Some(body) => (200, "OK", body.clone()),
+			None => (404, "Not Found", Bytes::new()),
+		};
+		let path = if path.is_empty() || path.ends_with('/') {
+			format!("{path}index.html")
+		} else {
+			path
+		};
+
+		FetchedResponse {
+			url: url.to_string(),
+			status,
+			status_text: status_text.to_string(),
+			headers: vec![("content-type".to_string(), mime_for(&path).to_string())],
+			body,
+			from_cache: false,
+		}
+	}
+}
+
+#[wasm_bindgen]
+impl BlitzBundle {
+	/// Reads a tar archive, including pax and gnu long names.
+	pub fn from_tar(data: &[u8]) -> Result<BlitzBundle, JsError> {
+		Ok(Self {
+			files: Arc::new(parse_tar(data).map_err(anyhow_to_obj)?),
+		})
+	}
+
+	/// Reads a zip archive with stored or deflated entries.
+	pub fn from_zip(data: &[u8]) -> Result<BlitzBundle, JsError> {
+		Ok(Self {
+			files: Arc::new(parse_zip(data).map_err(anyhow_to_obj)?),
+		})
+	}
+
+	pub fn paths(&self) -> Vec<String> {
+		let mut paths = self.files.keys().cloned().collect::<Vec<_>>();
+		paths.sort();
+		paths
+	}
+
+	pub fn get(&self, path: &str) -> Option<Vec<u8>> {
+		self.file(path).map(|x| x.to_vec())
+	}
+
+	/// Reads a file as UTF-8, like the HTML to create a document with.
+	pub fn get_text(&self, path: &str) -> Option<String> {
+		self.file(path)
+			.map(|x| String::from_utf8_lossy(x).into_owned())
+	}
+}
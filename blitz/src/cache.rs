@@ -0,0 +1,614 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use blitz_traits::net::Bytes;
+use js_sys::{Array, Date, Function, Promise, Reflect, Uint8Array};
+use wasm_bindgen::{JsCast, JsValue, prelude::wasm_bindgen};
+use wasm_bindgen_futures::{JsFuture, spawn_local};
+use web_sys::console;
+
+use crate::blitz_net::FetchedResponse;
+
+#[wasm_bindgen(typescript_custom_section)]
+const BLITZ_CACHE_STORAGE: &'static str = r#"
+interface BlitzCacheRecord {
+	url: string;
+	status: number;
+	statusText: string;
+	headers: [string, string][];
+	vary: [string, string | null][];
+	storedAt: number;
+	lifetime: number;
+	noCache: boolean;
+	mustRevalidate: boolean;
+	body: Uint8Array;
+}
+interface BlitzCacheStorage {
+	get(key: string): Promise<BlitzCacheRecord[] | undefined> | BlitzCacheRecord[] | undefined;
+	put(key: string, records: BlitzCacheRecord[]): Promise<void> | void;
+	delete(key: string): Promise<void> | void;
+	clear(): Promise<void> | void;
+}
+"#;
+
+#[wasm_bindgen]
+extern "C" {
+	/// A persistent store the host can put behind the in-memory cache, like one backed by
+	/// IndexedDB. Every method may return a promise.
+	#[wasm_bindgen(typescript_type = "BlitzCacheStorage")]
+	#[derive(Clone)]
+	pub type BlitzCacheStorage;
+}
+
+/// The directives of a `Cache-Control` header that a private cache cares about.
+#[derive(Default)]
+struct CacheControl {
+	no_store: bool,
+	no_cache: bool,
+	must_revalidate: bool,
+	max_age: Option<f64>,
+}
+
+impl CacheControl {
+	fn parse(value: Option<&str>) -> Self {
+		let mut ret = Self::default();
+		for directive in value.unwrap_or_default().split(',') {
+			let (name, arg) = match directive.split_once('=') {
+				Some((name, arg)) => (name, Some(arg.trim().trim_matches('"'))),
+				None => (directive, None),
+			};
+			match name.trim().to_ascii_lowercase().as_str() {
+				"no-store" => ret.no_store = true,
+				"no-cache" => ret.no_cache = true,
+				"must-revalidate" => ret.must_revalidate = true,
+				"max-age" => ret.max_age = arg.and_then(|x| x.parse().ok()),
+				_ => {}
+			}
+		}
+		ret
+	}
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+	headers
+		.iter()
+		.find(|(key, _)| key.eq_ignore_ascii_case(name))
+		.map(|(_, val)| val.as_str())
+}
+
+/// Parses an HTTP date into milliseconds since the epoch.
+fn parse_date(value: Option<&str>) -> Option<f64> {
+	Some(Date::parse(value?)).filter(|x| !x.is_nan())
+}
+
+#[derive(Clone)]
+struct CacheEntry {
+	response: FetchedResponse,
+	/// Request header values named by the response's `Vary`, which a request has to match for
+	/// the response to be reused.
+	vary: Vec<(String, Option<String>)>,
+	/// Wall clock time the response was received at, in milliseconds since the epoch.
+	stored_at: f64,
+	/// How long the response stays fresh after it was received, in milliseconds.
+	lifetime: f64,
+	/// The response has to be revalidated every time it's used.
+	no_cache: bool,
+	/// The response can't stand in for a failed revalidation once it's stale.
+	must_revalidate: bool,
+	/// Counter value the entry was last used at, for eviction.
+	last_used: u64,
+}
+
+impl CacheEntry {
+	/// Returns an entry for the response if it may be stored.
+	fn new(response: &FetchedResponse, request_headers: &[(String, String)]) -> Option<Self> {
+		if !matches!(response.status, 200 | 203) {
+			return None;
+		}
+
+		let control = CacheControl::parse(response.header("cache-control"));
+		let vary = response.header("vary").unwrap_or_default();
+		if control.no_store || vary.trim() == "*" {
+			return None;
+		}
+
+		let now = Date::now();
+		let date = parse_date(response.header("date")).unwrap_or(now);
+		let last_modified = parse_date(response.header("last-modified"));
+		// RFC 9111 4.2.2 suggests a tenth of the time since the response was last modified
+		// when the server doesn't say
+		let lifetime = match control.max_age {
+			Some(max_age) => max_age * 1000.0,
+			None => match parse_date(response.header("expires")) {
+				Some(expires) => expires - date,
+				None => last_modified.map_or(0.0, |x| (date - x).max(0.0) / 10.0),
+			},
+		};
+		let age = response
+			.header("age")
+			.and_then(|x| x.trim().parse::<f64>().ok())
+			.unwrap_or_default();
+
+		let entry = Self {
+			response: FetchedResponse {
+				from_cache: true,
+				..response.clone()
+			},
+			vary: vary
+				.split(',')
+				.map(|x| x.trim().to_ascii_lowercase())
+				.filter(|x| !x.is_empty())
+				.map(|name| {
+					let value = header(request_headers, &name).map(ToOwned::to_owned);
+					(name, value)
+				})
+				.collect(),
+			stored_at: now,
+			lifetime: lifetime - age * 1000.0,
+			no_cache: control.no_cache,
+			must_revalidate: control.must_revalidate,
+			last_used: 0,
+		};
+
+		// nothing to gain from keeping a stale response that can't be revalidated
+		(entry.is_fresh(now) || !entry.validators().is_empty()).then_some(entry)
+	}
+
+	fn size(&self) -> usize {
+		self.response.body.len()
+			+ self
+				.response
+				.headers
+				.iter()
+				.map(|(key, val)| key.len() + val.len())
+				.sum::<usize>()
+	}
+
+	fn is_fresh(&self, now: f64) -> bool {
+		!self.no_cache && now - self.stored_at < self.lifetime
+	}
+
+	fn matches(&self, request_headers: &[(String, String)]) -> bool {
+		self.vary
+			.iter()
+			.all(|(name, value)| header(request_headers, name) == value.as_deref())
+	}
+
+	/// Headers that ask the server whether the entry is still current.
+	fn validators(&self) -> Vec<(String, String)> {
+		let mut ret = Vec::new();
+		if let Some(etag) = self.response.header("etag") {
+			ret.push(("If-None-Match".to_string(), etag.to_string()));
+		}
+		if let Some(modified) = self.response.header("last-modified") {
+			ret.push(("If-Modified-Since".to_string(), modified.to_string()));
+		}
+		ret
+	}
+
+	fn to_js(&self) -> Result<JsValue, JsValue> {
+		fn pairs(pairs: impl Iterator<Item = (JsValue, JsValue)>) -> Array {
+			pairs.map(|(key, val)| Array::of2(&key, &val)).collect()
+		}
+
+		let obj = js_sys::Object::new();
+		let set = |key: &str, val: JsValue| Reflect::set(&obj, &key.into(), &val);
+		set("url", self.response.url.as_str().into())?;
+		set("status", self.response.status.into())?;
+		set("statusText", self.response.status_text.as_str().into())?;
+		set(
+			"headers",
+			pairs(
+				self.response
+					.headers
+					.iter()
+					.map(|(key, val)| (key.into(), val.into())),
+			)
+			.into(),
+		)?;
+		set(
+			"vary",
+			pairs(
+				self.vary
+					.iter()
+					.map(|(key, val)| (key.into(), val.as_deref().into())),
+			)
+			.into(),
+		)?;
+		set("storedAt", self.stored_at.into())?;
+		set("lifetime", self.lifetime.into())?;
+		set("noCache", self.no_cache.into())?;
+		set("mustRevalidate", self.must_revalidate.into())?;
+		set(
+			"body",
+			Uint8Array::new_from_slice(&self.response.body).into(),
+		)?;
+		Ok(obj.into())
+	}
+
+	fn from_js(record: &JsValue) -> Result<Self, JsValue> {
+		let get = |key: &str| Reflect::get(record, &key.into());
+		let pairs = |val: JsValue| -> Vec<(String, Option<String>)> {
+			Array::from(&val)
+				.iter()
+				.filter_map(|entry| {
+					let entry: Array = entry.unchecked_into();
+					Some((entry.at(0).as_string()?, entry.at(1).as_string()))
+				})
+				.collect()
+		};
+
+		Ok(Self {
+			response: FetchedResponse {
+				url: get("url")?.as_string().unwrap_or_default(),
+				status: get("status")?.as_f64().unwrap_or(200.0) as u16,
+				status_text: get("statusText")?.as_string().unwrap_or_default(),
+				headers: pairs(get("headers")?)
+					.into_iter()
+					.filter_map(|(key, val)| Some((key, val?)))
+					.collect(),
+				body: Bytes::from(Uint8Array::new(&get("body")?).to_vec()),
+				from_cache: true,
+			},
+			vary: pairs(get("vary")?),
+			stored_at: get("storedAt")?.as_f64().unwrap_or_default(),
+			lifetime: get("lifetime")?.as_f64().unwrap_or_default(),
+			no_cache: get("noCache")?.is_truthy(),
+			must_revalidate: get("mustRevalidate")?.is_truthy(),
+			last_used: 0,
+		})
+	}
+}
+
+/// What the cache has for a request.
+pub(crate) enum CacheLookup {
+	/// A fresh response that can be used as is.
+	Fresh(FetchedResponse),
+	/// A stale response, and the headers to revalidate it with.
+	Stale(Vec<(String, String)>),
+	Miss,
+}
+
+/// Size limits of the in-memory cache, in bytes.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy)]
+pub struct BlitzCacheLimits {
+	pub max_bytes: usize,
+	/// Responses bigger than this aren't stored at all.
+	pub max_entry_bytes: usize,
+}
+
+impl Default for BlitzCacheLimits {
+	fn default() -> Self {
+		Self {
+			max_bytes: 64 * 1024 * 1024,
+			max_entry_bytes: 8 * 1024 * 1024,
+		}
+	}
+}
+
+#[wasm_bindgen]
+impl BlitzCacheLimits {
+	#[wasm_bindgen(constructor)]
+	pub fn new(max_bytes: usize, max_entry_bytes: usize) -> Self {
+		Self {
+			max_bytes,
+			max_entry_bytes,
+		}
+	}
+}
+
+#[derive(Default)]
+struct CacheState {
+	/// Every stored variant of a response, by key.
+	entries: HashMap<String, Vec<CacheEntry>>,
+	size: usize,
+	limits: BlitzCacheLimits,
+	clock: u64,
+	storage: Option<BlitzCacheStorage>,
+	/// Requests being fetched by key, with the promise followers wait on and its resolver.
+	inflight: HashMap<String, (Promise, Function)>,
+}
+
+impl CacheState {
+	fn remove(&mut self, key: &str) {
+		if let Some(variants) = self.entries.remove(key) {
+			self.size -= variants.iter().map(CacheEntry::size).sum::<usize>();
+		}
+	}
+
+	fn insert(&mut self, key: &str, entry: CacheEntry) {
+		let variants = self.entries.entry(key.to_string()).or_default();
+		if let Some(i) = variants.iter().position(|x| x.vary == entry.vary) {
+			self.size -= variants.remove(i).size();
+		}
+		self.size += entry.size();
+		variants.push(entry);
+		self.evict();
+	}
+
+	/// Drops least recently used variants until the cache fits its limit.
+	fn evict(&mut self) {
+		while self.size > self.limits.max_bytes {
+			let Some((key, index)) = self
+				.entries
+				.iter()
+				.flat_map(|(key, variants)| {
+					variants
+						.iter()
+						.enumerate()
+						.map(move |(i, entry)| (entry.last_used, key, i))
+				})
+				.min()
+				.map(|(_, key, i)| (key.clone(), i))
+			else {
+				break;
+			};
+
+			let variants = self.entries.get_mut(&key).unwrap();
+			self.size -= variants.remove(index).size();
+			if variants.is_empty() {
+				self.entries.remove(&key);
+			}
+		}
+	}
+}
+
+/// Marks a request as the one fetching its key, letting followers go once it's dropped.
+pub(crate) struct InflightGuard<'a> {
+	cache: &'a HttpCache,
+	key: String,
+}
+
+impl Drop for InflightGuard<'_> {
+	fn drop(&mut self) {
+		let inflight = self.cache.state.lock().unwrap().inflight.remove(&self.key);
+		if let Some((_, resolve)) = inflight {
+			let _ = resolve.call0(&JsValue::NULL);
+		}
+	}
+}
+
+/// A private HTTP cache following the parts of RFC 9111 a browser needs: `Cache-Control`,
+/// `Expires`, heuristic freshness, revalidation with `ETag` and `Last-Modified`, and `Vary`.
+///
+/// Responses live in memory and, if the host set one, in a [`BlitzCacheStorage`] as well.
+#[derive(Default)]
+pub struct HttpCache {
+	state: Mutex<CacheState>,
+}
+unsafe impl Send for HttpCache {}
+unsafe impl Sync for HttpCache {}
+
+impl HttpCache {
+	/// The key responses to a request are stored under, or none if they can't be cached.
+	pub(crate) fn key(method: &str, url: &str) -> Option<String> {
+		(method == "GET").then(|| url.split('#').next().unwrap_or_default().to_string())
+	}
+
+	pub fn limits(&self) -> BlitzCacheLimits {
+		self.state.lock().unwrap().limits
+	}
+
+	pub fn set_limits(&self, limits: BlitzCacheLimits) {
+		let mut state = self.state.lock().unwrap();
+		state.limits = limits;
+		state.evict();
+	}
+
+	/// Size of the in-memory cache in bytes.
+	pub fn size(&self) -> usize {
+		self.state.lock().unwrap().size
+	}
+
+	pub fn set_storage(&self, storage: Option<BlitzCacheStorage>) {
+		self.state.lock().unwrap().storage = storage;
+	}
+
+	/// Forgets every stored response, including the ones in the host's storage.
+	pub fn clear(&self) {
+		let storage = {
+			let mut state = self.state.lock().unwrap();
+			state.entries.clear();
+			state.size = 0;
+			state.storage.clone()
+		};
+		if let Some(storage) = storage {
+			Self::call_storage(storage, "clear", &[]);
+		}
+	}
+
+	/// Calls a method of the host's storage in the background, logging failures.
+	fn call_storage(storage: BlitzCacheStorage, method: &'static str, args: &[JsValue]) {
+		let args = args.iter().collect::<Array>();
+		spawn_local(async move {
+			let result = async {
+				let func: Function = Reflect::get(&storage, &method.into())?.dyn_into()?;
+				JsFuture::from(Promise::resolve(&func.apply(&storage, &args)?)).await
+			};
+			if let Err(err) = result.await {
+				console::error_3(&"cache storage".into(), &method.into(), &err);
+			}
+		});
+	}
+
+	/// Loads the variants the host's storage has for `key` into memory, if there is a storage
+	/// and memory has none.
+	async fn load(&self, key: &str) -> Result<(), JsValue> {
+		let storage = {
+			let state = self.state.lock().unwrap();
+			if state.entries.contains_key(key) {
+				return Ok(());
+			}
+			state.storage.clone()
+		};
+		let Some(storage) = storage else {
+			return Ok(());
+		};
+
+		let get: Function = Reflect::get(&storage, &"get".into())?.dyn_into()?;
+		let records = JsFuture::from(Promise::resolve(&get.call1(&storage, &key.into())?)).await?;
+		if records.is_undefined() || records.is_null() {
+			return Ok(());
+		}
+
+		let mut state = self.state.lock().unwrap();
+		for record in Array::from(&records).iter() {
+			state.insert(key, CacheEntry::from_js(&record)?);
+		}
+		Ok(())
+	}
+
+	pub(crate) async fn lookup(
+		&self,
+		key: &str,
+		request_headers: &[(String, String)],
+	) -> CacheLookup {
+		let control = CacheControl::parse(header(request_headers, "cache-control"));
+		if control.no_store {
+			return CacheLookup::Miss;
+		}
+
+		if let Err(err) = self.load(key).await {
+			console::error_2(&"failed to load from cache storage:".into(), &err);
+		}
+
+		let mut state = self.state.lock().unwrap();
+		state.clock += 1;
+		let clock = state.clock;
+		let Some(entry) = state
+			.entries
+			.get_mut(key)
+			.and_then(|x| x.iter_mut().find(|x| x.matches(request_headers)))
+		else {
+			return CacheLookup::Miss;
+		};
+		entry.last_used = clock;
+
+		if !control.no_cache && entry.is_fresh(Date::now()) {
+			CacheLookup::Fresh(entry.response.clone())
+		} else {
+			match entry.validators() {
+				validators if validators.is_empty() => CacheLookup::Miss,
+				validators => CacheLookup::Stale(validators),
+			}
+		}
+	}
+
+	/// Waits for a request already fetching `key` to finish, or returns a guard that makes
+	/// this request the one others wait for.
+	pub(crate) async fn join(&self, key: &str) -> Option<InflightGuard<'_>> {
+		let pending = {
+			let mut state = self.state.lock().unwrap();
+			match state.inflight.get(key) {
+				Some((promise, _)) => promise.clone(),
+				None => {
+					let mut resolve = None;
+					let promise = Promise::new(&mut |res, _| resolve = Some(res));
+					state
+						.inflight
+						.insert(key.to_string(), (promise, resolve.unwrap()));
+					return Some(InflightGuard {
+						cache: self,
+						key: key.to_string(),
+					});
+				}
+			}
+		};
+
+		let _ = JsFuture::from(pending).await;
+		None
+	}
+
+	/// Stores a response if it may be, replacing what was stored for the same variant.
+	pub(crate) fn store(
+		&self,
+		key: &str,
+		request_headers: &[(String, String)],
+		response: &FetchedResponse,
+	) {
+		let mut state = self.state.lock().unwrap();
+		let Some(mut entry) = CacheEntry::new(response, request_headers)
+			.filter(|x| x.size() <= state.limits.max_entry_bytes)
+		else {
+			// a response that can't be stored also means the old ones are outdated
+			state.remove(key);
+			self.persist(&state, key);
+			return;
+		};
+
+		state.clock += 1;
+		entry.last_used = state.clock;
+		state.insert(key, entry);
+		self.persist(&state, key);
+	}
+
+	/// The stored variant for a request whose revalidation couldn't reach the server, if it may
+	/// be used stale (RFC 9111 4.2.4).
+	pub(crate) fn stale(
+		&self,
+		key: &str,
+		request_headers: &[(String, String)],
+	) -> Option<FetchedResponse> {
+		let state = self.state.lock().unwrap();
+		state
+			.entries
+			.get(key)?
+			.iter()
+			.find(|x| x.matches(request_headers) && !x.no_cache && !x.must_revalidate)
+			.map(|x| x.response.clone())
+	}
+
+	/// Updates the stored variant with the headers of a `304 Not Modified` and returns it.
+	pub(crate) fn revalidated(
+		&self,
+		key: &str,
+		request_headers: &[(String, String)],
+		not_modified: &FetchedResponse,
+	) -> Option<FetchedResponse> {
+		let mut state = self.state.lock().unwrap();
+		let mut response = state
+			.entries
+			.get(key)?
+			.iter()
+			.find(|x| x.matches(request_headers))?
+			.response
+			.clone();
+
+		for (name, value) in &not_modified.headers {
+			if name == "content-length" {
+				continue;
+			}
+			response.headers.retain(|(key, _)| key != name);
+			response.headers.push((name.clone(), value.clone()));
+		}
+
+		if let Some(mut entry) = CacheEntry::new(&response, request_headers) {
+			state.clock += 1;
+			entry.last_used = state.clock;
+			state.insert(key, entry);
+			self.persist(&state, key);
+		}
+		Some(response)
+	}
+
+	/// Writes the variants of `key` to the host's storage, if there is one, or deletes them
+	/// there if there are none.
+	fn persist(&self, state: &CacheState, key: &str) {
+		let Some(storage) = state.storage.clone() else {
+			return;
+		};
+		let Some(variants) = state.entries.get(key) else {
+			Self::call_storage(storage, "delete", &[key.into()]);
+			return;
+		};
+
+		match variants
+			.iter()
+			.map(CacheEntry::to_js)
+			.collect::<Result<Array, _>>()
+		{
+			Ok(records) => Self::call_storage(storage, "put", &[key.into(), records.into()]),
+			Err(err) => console::error_2(&"failed to serialize cache entry:".into(), &err),
+		}
+	}
+}
@@ -1,37 +1,329 @@
-use anyhow::{Context, Result};
+use std::{cell::RefCell, rc::Rc};
+
+use anyhow::{Context, Result, anyhow, bail};
+use anyrender::{ImageRenderer, PaintScene};
+use anyrender_vello_cpu::VelloCpuImageRenderer;
+use futures_channel::oneshot;
 use vello::{
-	AaConfig, AaSupport, RenderParams, Renderer, RendererOptions, Scene, peniko::color::palette,
+	AaConfig, AaSupport, RenderParams, Renderer, RendererOptions, Scene,
+	kurbo::{Affine, Rect},
+	peniko::{Color, Fill},
 };
-use web_sys::OffscreenCanvas;
+use wasm_bindgen::{Clamped, JsCast, prelude::wasm_bindgen};
+use web_sys::{ImageData, OffscreenCanvas, OffscreenCanvasRenderingContext2d, console};
 use wgpu::{
-	Device, DeviceDescriptor, InstanceDescriptor, PowerPreference, Queue, RequestAdapterOptions,
-	Surface, SurfaceConfiguration, SurfaceTarget, TextureFormat, TextureUsages,
-	TextureViewDescriptor,
+	Adapter, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+	BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType,
+	BufferDescriptor, BufferUsages, COPY_BYTES_PER_ROW_ALIGNMENT, ColorTargetState, ColorWrites,
+	CommandEncoderDescriptor, CompositeAlphaMode, Device, DeviceDescriptor, Extent3d,
+	FragmentState, Instance, InstanceDescriptor, LoadOp, MapMode, MultisampleState, Operations,
+	PipelineLayoutDescriptor, PowerPreference, PresentMode, PrimitiveState, Queue,
+	RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor,
+	RequestAdapterOptions, ShaderModuleDescriptor, ShaderSource, ShaderStages, StoreOp, Surface,
+	SurfaceConfiguration, SurfaceTarget, TexelCopyBufferInfo, TexelCopyBufferLayout,
+	TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType, TextureUsages,
+	TextureView, TextureViewDescriptor, TextureViewDimension, VertexState,
 };
 
-pub struct CanvasVelloScene {
-	device: Device,
-	queue: Queue,
-	renderer: Renderer,
-	surface: Surface<'static>,
-	scene: Scene,
+use crate::{anyhow_to_obj, anyrender::VelloScenePainter};
+
+#[wasm_bindgen]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BlitzRenderBackend {
+	/// Use WebGPU if an adapter is available, otherwise rasterize on the CPU.
+	#[default]
+	Auto,
+	Gpu,
+	Cpu,
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BlitzAntialiasing {
+	Area,
+	Msaa8,
+	#[default]
+	Msaa16,
+}
+impl From<BlitzAntialiasing> for AaConfig {
+	fn from(value: BlitzAntialiasing) -> Self {
+		match value {
+			BlitzAntialiasing::Area => Self::Area,
+			BlitzAntialiasing::Msaa8 => Self::Msaa8,
+			BlitzAntialiasing::Msaa16 => Self::Msaa16,
+		}
+	}
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BlitzPresentMode {
+	#[default]
+	AutoVsync,
+	AutoNoVsync,
+	Fifo,
+	FifoRelaxed,
+	Immediate,
+	Mailbox,
+}
+impl From<BlitzPresentMode> for PresentMode {
+	fn from(value: BlitzPresentMode) -> Self {
+		match value {
+			BlitzPresentMode::AutoVsync => Self::AutoVsync,
+			BlitzPresentMode::AutoNoVsync => Self::AutoNoVsync,
+			BlitzPresentMode::Fifo => Self::Fifo,
+			BlitzPresentMode::FifoRelaxed => Self::FifoRelaxed,
+			BlitzPresentMode::Immediate => Self::Immediate,
+			BlitzPresentMode::Mailbox => Self::Mailbox,
+		}
+	}
+}
+
+/// How frames are rasterized and presented. Only `background` applies to the cpu backend.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlitzRendererOptions {
+	pub antialiasing: BlitzAntialiasing,
+	/// Color beneath the page as `0xRRGGBBAA`. Anything less than opaque lets the content behind
+	/// the canvas show through.
+	pub background: u32,
+	pub present_mode: BlitzPresentMode,
+	pub frame_latency: u32,
+}
+
+impl Default for BlitzRendererOptions {
+	fn default() -> Self {
+		Self {
+			antialiasing: BlitzAntialiasing::default(),
+			background: 0xffffffff,
+			present_mode: BlitzPresentMode::default(),
+			frame_latency: 2,
+		}
+	}
+}
+
+#[wasm_bindgen]
+impl BlitzRendererOptions {
+	/// Msaa16 on an opaque white background, presented with vsync.
+	#[wasm_bindgen(constructor)]
+	pub fn new() -> Self {
+		Self::default()
+	}
+}
+
+/// Converts a `0xRRGGBBAA` color as passed from JS.
+pub fn color_from_rgba(color: u32) -> Color {
+	let [r, g, b, a] = color.to_be_bytes();
+	Color::from_rgba8(r, g, b, a)
+}
+
+impl BlitzRendererOptions {
+	pub fn background_color(&self) -> Color {
+		color_from_rgba(self.background)
+	}
+
+	fn is_opaque(&self) -> bool {
+		self.background & 0xff == 0xff
+	}
+}
+
+/// Something that paints a frame, independent of the backend it is painted with.
+pub trait CanvasPaint {
+	fn paint(&self, scene: &mut impl PaintScene, width: u32, height: u32, scale: f32);
+}
+
+pub enum CanvasScene {
+	Gpu(CanvasVelloScene),
+	Cpu(CanvasCpuScene),
+}
+impl CanvasScene {
+	/// Creates a scene for `backend`, rendering with `gpu` if given instead of creating a new
+	/// device.
+	pub async fn new(
+		canvas: OffscreenCanvas,
+		scale: f32,
+		backend: BlitzRenderBackend,
+		options: BlitzRendererOptions,
+		gpu: Option<BlitzGpuContext>,
+	) -> Result<CanvasScene> {
+		let gpu = match (backend, gpu) {
+			(BlitzRenderBackend::Cpu, _) => None,
+			(_, Some(gpu)) => Some(Ok(gpu)),
+			(_, None) => Some(BlitzGpuContext::create().await),
+		};
+
+		Ok(match (backend, gpu) {
+			(BlitzRenderBackend::Gpu, Some(gpu)) => {
+				Self::Gpu(CanvasVelloScene::new(gpu?, canvas, scale, options)?)
+			}
+			(BlitzRenderBackend::Auto, Some(Ok(gpu))) => {
+				Self::Gpu(CanvasVelloScene::new(gpu, canvas, scale, options)?)
+			}
+			(BlitzRenderBackend::Auto, Some(Err(err))) => {
+				console::warn_2(
+					&"webgpu unavailable, falling back to cpu rendering:".into(),
+					&format!("{err:?}").into(),
+				);
+				Self::Cpu(CanvasCpuScene::new(canvas, scale, options)?)
+			}
+			_ => Self::Cpu(CanvasCpuScene::new(canvas, scale, options)?),
+		})
+	}
+
+	pub fn gpu(&self) -> Option<&BlitzGpuContext> {
+		match self {
+			Self::Gpu(scene) => Some(scene.gpu()),
+			Self::Cpu(_) => None,
+		}
+	}
+
+	pub fn options(&self) -> BlitzRendererOptions {
+		match self {
+			Self::Gpu(scene) => scene.options,
+			Self::Cpu(scene) => scene.options,
+		}
+	}
+
+	pub fn set_options(&mut self, options: BlitzRendererOptions) {
+		match self {
+			Self::Gpu(scene) => scene.set_options(options),
+			Self::Cpu(scene) => scene.options = options,
+		}
+	}
+
+	pub fn backend(&self) -> BlitzRenderBackend {
+		match self {
+			Self::Gpu(_) => BlitzRenderBackend::Gpu,
+			Self::Cpu(_) => BlitzRenderBackend::Cpu,
+		}
+	}
+
+	/// Adapts to a resized canvas or a new one, keeping the device and renderer.
+	pub fn resize(&mut self, canvas: OffscreenCanvas, scale: f32) -> Result<()> {
+		match self {
+			Self::Gpu(scene) => scene.resize(canvas, scale),
+			Self::Cpu(scene) => scene.resize(canvas, scale),
+		}
+	}
+
+	pub fn render(&mut self, func: &impl CanvasPaint) -> Result<()> {
+		match self {
+			Self::Gpu(scene) => scene.render(func),
+			Self::Cpu(scene) => scene.render(func),
+		}
+	}
+
+	/// Paints into an in-memory RGBA8 buffer instead of the canvas.
+	pub async fn render_to_rgba(
+		&mut self,
+		width: u32,
+		height: u32,
+		scale: f32,
+		func: &impl CanvasPaint,
+	) -> Result<Vec<u8>> {
+		match self {
+			Self::Gpu(scene) => scene.render_to_rgba(width, height, scale, func).await,
+			Self::Cpu(scene) => {
+				// vello_cpu stores dimensions as u16
+				check_image_size(width, height, u16::MAX as u32)?;
+				Ok(rasterize_cpu(
+					width,
+					height,
+					scale,
+					scene.options.background_color(),
+					func,
+				))
+			}
+		}
+	}
+}
+
+/// Rejects image sizes that are empty or larger than `max` on either side.
+fn check_image_size(width: u32, height: u32, max: u32) -> Result<()> {
+	if width == 0 || height == 0 || width > max || height > max {
+		bail!("cannot render a {width}x{height} image, the limit is {max}x{max}");
+	}
+	Ok(())
+}
+
+fn check_canvas_size(canvas: &OffscreenCanvas) -> Result<()> {
+	if canvas.width() == 0 || canvas.height() == 0 {
+		bail!(
+			"cannot render to a {}x{} canvas",
+			canvas.width(),
+			canvas.height()
+		);
+	}
+	Ok(())
+}
+
+fn paint_background(scene: &mut impl PaintScene, width: u32, height: u32, color: Color) {
+	// vello_cpu has no base color, so paint the same background the gpu backend uses
+	scene.fill(
+		Fill::NonZero,
+		Affine::IDENTITY,
+		color,
+		None,
+		&Rect::new(0.0, 0.0, width as f64, height as f64),
+	);
+}
+
+/// Rasterizes a frame on the CPU without needing a canvas or a GPU.
+/// Turns premultiplied RGBA, which vello_cpu renders, into the straight alpha that image data
+/// and the gpu backend use.
+fn unpremultiply(rgba: &mut [u8]) {
+	for px in rgba.chunks_exact_mut(4) {
+		let alpha = px[3] as u16;
+		if alpha != 0 && alpha != 255 {
+			for c in &mut px[..3] {
+				*c = ((*c as u16 * 255 + alpha / 2) / alpha).min(255) as u8;
+			}
+		}
+	}
+}
+
+pub fn rasterize_cpu(
 	width: u32,
 	height: u32,
 	scale: f32,
+	background: Color,
+	func: &impl CanvasPaint,
+) -> Vec<u8> {
+	let mut renderer = VelloCpuImageRenderer::new(width, height);
+	let mut buffer = Vec::with_capacity(width as usize * height as usize * 4);
+	renderer.render_to_vec(
+		|scene| {
+			paint_background(scene, width, height, background);
+			func.paint(scene, width, height, scale);
+		},
+		&mut buffer,
+	);
+	unpremultiply(&mut buffer);
+	buffer
 }
-impl CanvasVelloScene {
-	pub async fn new(canvas: OffscreenCanvas, scale: f32) -> Result<CanvasVelloScene> {
-		let width = canvas.width();
-		let height = canvas.height();
 
-		let instance = wgpu::Instance::new(&InstanceDescriptor::default());
-		let surface = instance
-			.create_surface(SurfaceTarget::OffscreenCanvas(canvas))
-			.context("failed to create surface")?;
+struct GpuContextInner {
+	instance: Instance,
+	adapter: Adapter,
+	device: Device,
+	queue: Queue,
+	renderer: RefCell<Renderer>,
+}
+
+/// A WebGPU device and vello renderer, created once and shared by every canvas rendered with it.
+#[wasm_bindgen]
+#[derive(Clone)]
+pub struct BlitzGpuContext(Rc<GpuContextInner>);
+
+impl BlitzGpuContext {
+	pub async fn create() -> Result<Self> {
+		// the adapter is requested before any surface is created so that a failure here leaves the
+		// canvas without a context, letting the cpu backend claim it instead
+		let instance = Instance::new(&InstanceDescriptor::default());
 		let adapter = instance
 			.request_adapter(&RequestAdapterOptions {
 				power_preference: PowerPreference::None,
-				compatible_surface: Some(&surface),
+				compatible_surface: None,
 				force_fallback_adapter: false,
 			})
 			.await
@@ -45,18 +337,6 @@ impl CanvasVelloScene {
 			.await
 			.context("failed to request device")?;
 
-		let surface_config = SurfaceConfiguration {
-			usage: TextureUsages::STORAGE_BINDING,
-			width,
-			height,
-			format: TextureFormat::Rgba8Unorm,
-			view_formats: vec![TextureFormat::Rgba8UnormSrgb],
-			alpha_mode: wgpu::CompositeAlphaMode::Auto,
-			desired_maximum_frame_latency: 2,
-			present_mode: wgpu::PresentMode::AutoVsync,
-		};
-		surface.configure(&device, &surface_config);
-
 		let renderer = Renderer::new(
 			&device,
 			RendererOptions {
@@ -68,19 +348,349 @@ impl CanvasVelloScene {
 		)
 		.context("failed to create renderer")?;
 
-		Ok(Self {
+		Ok(Self(Rc::new(GpuContextInner {
+			instance,
+			adapter,
 			device,
 			queue,
+			renderer: RefCell::new(renderer),
+		})))
+	}
+
+	fn render_to_texture(
+		&self,
+		scene: &Scene,
+		view: &TextureView,
+		params: &RenderParams,
+	) -> Result<()> {
+		self.0
+			.renderer
+			.borrow_mut()
+			.render_to_texture(&self.0.device, &self.0.queue, scene, view, params)
+			.context("failed to render")
+	}
+}
+
+#[wasm_bindgen]
+impl BlitzGpuContext {
+	#[wasm_bindgen]
+	pub async fn new() -> Result<BlitzGpuContext, JsError> {
+		Self::create().await.map_err(anyhow_to_obj)
+	}
+}
+
+const PREMULTIPLY_SHADER: &str = r#"
+@group(0) @binding(0) var source: texture_2d<f32>;
+
+@vertex
+fn vs(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+	// one triangle that covers the whole target
+	let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+	return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+	let color = textureLoad(source, vec2<i32>(position.xy), 0);
+	return vec4<f32>(color.rgb * color.a, color.a);
+}
+"#;
+
+/// Copies what vello rendered onto the surface, premultiplying it on the way. Vello's output
+/// has straight alpha, but WebGPU canvases can only be composited as premultiplied.
+struct PremultiplyBlit {
+	pipeline: RenderPipeline,
+	layout: BindGroupLayout,
+	/// What vello renders into, bound for the blit, and its size.
+	source: Option<(TextureView, BindGroup, u32, u32)>,
+}
+
+impl PremultiplyBlit {
+	fn new(device: &Device) -> Self {
+		let module = device.create_shader_module(ShaderModuleDescriptor {
+			label: Some("blitz premultiply"),
+			source: ShaderSource::Wgsl(PREMULTIPLY_SHADER.into()),
+		});
+		let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+			label: Some("blitz premultiply"),
+			entries: &[BindGroupLayoutEntry {
+				binding: 0,
+				visibility: ShaderStages::FRAGMENT,
+				ty: BindingType::Texture {
+					sample_type: TextureSampleType::Float { filterable: false },
+					view_dimension: TextureViewDimension::D2,
+					multisampled: false,
+				},
+				count: None,
+			}],
+		});
+		let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+			label: Some("blitz premultiply"),
+			bind_group_layouts: &[&layout],
+			push_constant_ranges: &[],
+		});
+		let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+			label: Some("blitz premultiply"),
+			layout: Some(&pipeline_layout),
+			vertex: VertexState {
+				module: &module,
+				entry_point: Some("vs"),
+				compilation_options: Default::default(),
+				buffers: &[],
+			},
+			fragment: Some(FragmentState {
+				module: &module,
+				entry_point: Some("fs"),
+				compilation_options: Default::default(),
+				targets: &[Some(ColorTargetState {
+					format: TextureFormat::Rgba8Unorm,
+					blend: None,
+					write_mask: ColorWrites::ALL,
+				})],
+			}),
+			primitive: PrimitiveState::default(),
+			depth_stencil: None,
+			multisample: MultisampleState::default(),
+			multiview: None,
+			cache: None,
+		});
+
+		Self {
+			pipeline,
+			layout,
+			source: None,
+		}
+	}
+
+	/// The texture to render a `width` by `height` frame into before it's drawn.
+	fn source(&mut self, device: &Device, width: u32, height: u32) -> TextureView {
+		if !matches!(&self.source, Some((_, _, w, h)) if (*w, *h) == (width, height)) {
+			let texture = device.create_texture(&TextureDescriptor {
+				label: Some("blitz premultiply source"),
+				size: Extent3d {
+					width,
+					height,
+					depth_or_array_layers: 1,
+				},
+				mip_level_count: 1,
+				sample_count: 1,
+				dimension: TextureDimension::D2,
+				format: TextureFormat::Rgba8Unorm,
+				usage: TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING,
+				view_formats: &[],
+			});
+			let view = texture.create_view(&TextureViewDescriptor::default());
+			let bind_group = device.create_bind_group(&BindGroupDescriptor {
+				label: Some("blitz premultiply"),
+				layout: &self.layout,
+				entries: &[BindGroupEntry {
+					binding: 0,
+					resource: BindingResource::TextureView(&view),
+				}],
+			});
+			self.source = Some((view, bind_group, width, height));
+		}
+		self.source.as_ref().unwrap().0.clone()
+	}
+
+	/// Draws the source onto `target`, premultiplied.
+	fn draw(&self, device: &Device, queue: &Queue, target: &TextureView) {
+		let Some((_, bind_group, _, _)) = &self.source else {
+			return;
+		};
+		let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+			label: Some("blitz premultiply"),
+		});
+		{
+			let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+				label: Some("blitz premultiply"),
+				color_attachments: &[Some(RenderPassColorAttachment {
+					view: target,
+					depth_slice: None,
+					resolve_target: None,
+					ops: Operations {
+						load: LoadOp::Clear(wgpu::Color::TRANSPARENT),
+						store: StoreOp::Store,
+					},
+				})],
+				depth_stencil_attachment: None,
+				timestamp_writes: None,
+				occlusion_query_set: None,
+			});
+			pass.set_pipeline(&self.pipeline);
+			pass.set_bind_group(0, bind_group, &[]);
+			pass.draw(0..3, 0..1);
+		}
+		queue.submit([encoder.finish()]);
+	}
+}
+
+pub struct CanvasVelloScene {
+	gpu: BlitzGpuContext,
+	canvas: OffscreenCanvas,
+	surface: Surface<'static>,
+	surface_config: SurfaceConfiguration,
+	present_modes: Vec<PresentMode>,
+	alpha_modes: Vec<CompositeAlphaMode>,
+	/// Set when the surface composites premultiplied alpha.
+	premultiply: Option<PremultiplyBlit>,
+	scene: Scene,
+	options: BlitzRendererOptions,
+	width: u32,
+	height: u32,
+	scale: f32,
+}
+impl CanvasVelloScene {
+	pub fn new(
+		gpu: BlitzGpuContext,
+		canvas: OffscreenCanvas,
+		scale: f32,
+		options: BlitzRendererOptions,
+	) -> Result<CanvasVelloScene> {
+		let width = canvas.width();
+		let height = canvas.height();
+
+		let surface = gpu
+			.0
+			.instance
+			.create_surface(SurfaceTarget::OffscreenCanvas(canvas.clone()))
+			.context("failed to create surface")?;
+
+		let capabilities = surface.get_capabilities(&gpu.0.adapter);
+		let surface_config = SurfaceConfiguration {
+			usage: TextureUsages::STORAGE_BINDING,
+			width,
+			height,
+			format: TextureFormat::Rgba8Unorm,
+			view_formats: vec![TextureFormat::Rgba8UnormSrgb],
+			alpha_mode: CompositeAlphaMode::Auto,
+			desired_maximum_frame_latency: 2,
+			present_mode: PresentMode::AutoVsync,
+		};
+
+		let mut scene = Self {
+			gpu,
+			canvas,
 			surface,
-			renderer,
+			surface_config,
+			present_modes: capabilities.present_modes,
+			alpha_modes: capabilities.alpha_modes,
+			premultiply: None,
 			scene: Scene::new(),
+			options,
 			width,
 			height,
 			scale,
+		};
+		scene.set_options(options);
+		Ok(scene)
+	}
+
+	pub fn gpu(&self) -> &BlitzGpuContext {
+		&self.gpu
+	}
+
+	pub fn resize(&mut self, canvas: OffscreenCanvas, scale: f32) -> Result<()> {
+		check_canvas_size(&canvas)?;
+		let recreated = canvas != self.canvas;
+		if recreated {
+			self.surface = self
+				.gpu
+				.0
+				.instance
+				.create_surface(SurfaceTarget::OffscreenCanvas(canvas.clone()))
+				.context("failed to create surface")?;
+			self.canvas = canvas;
+			self.alpha_modes = self
+				.surface
+				.get_capabilities(&self.gpu.0.adapter)
+				.alpha_modes;
+		}
+
+		self.width = self.canvas.width();
+		self.height = self.canvas.height();
+		self.scale = scale;
+		self.surface_config.width = self.width;
+		self.surface_config.height = self.height;
+		if recreated {
+			// the new surface may support other alpha modes
+			self.set_options(self.options);
+		} else {
+			self.surface
+				.configure(&self.gpu.0.device, &self.surface_config);
+		}
+
+		Ok(())
+	}
+
+	/// Reconfigures the surface for new options. This is cheap enough to do between frames.
+	pub fn set_options(&mut self, options: BlitzRendererOptions) {
+		let mut present_mode = options.present_mode.into();
+		// the auto modes always resolve to something supported, the others must be checked
+		if !matches!(
+			present_mode,
+			PresentMode::AutoVsync | PresentMode::AutoNoVsync
+		) && !self.present_modes.contains(&present_mode)
+		{
+			console::warn_1(
+				&format!("present mode {present_mode:?} is unsupported, using vsync").into(),
+			);
+			present_mode = PresentMode::AutoVsync;
+		}
+
+		self.surface_config.present_mode = present_mode;
+		self.surface_config.desired_maximum_frame_latency = options.frame_latency.max(1);
+		self.surface_config.alpha_mode = if options.is_opaque() {
+			CompositeAlphaMode::Auto
+		} else if self
+			.alpha_modes
+			.contains(&CompositeAlphaMode::PostMultiplied)
+		{
+			CompositeAlphaMode::PostMultiplied
+		} else if self
+			.alpha_modes
+			.contains(&CompositeAlphaMode::PreMultiplied)
+		{
+			CompositeAlphaMode::PreMultiplied
+		} else {
+			console::warn_1(&"the canvas can't be transparent, drawing it opaque".into());
+			CompositeAlphaMode::Auto
+		};
+		let premultiply = self.surface_config.alpha_mode == CompositeAlphaMode::PreMultiplied;
+		if premultiply != self.premultiply.is_some() {
+			self.premultiply = premultiply.then(|| PremultiplyBlit::new(&self.gpu.0.device));
+		}
+		// the blit draws onto the surface, vello writes to it directly otherwise
+		self.surface_config.usage = if premultiply {
+			TextureUsages::RENDER_ATTACHMENT
+		} else {
+			TextureUsages::STORAGE_BINDING
+		};
+		self.surface
+			.configure(&self.gpu.0.device, &self.surface_config);
+		self.options = options;
+	}
+
+	pub fn scale(&self) -> f32 {
+		self.scale
+	}
+
+	pub fn options(&self) -> BlitzRendererOptions {
+		self.options
+	}
+
+	pub fn render(&mut self, func: &impl CanvasPaint) -> anyhow::Result<()> {
+		self.render_scene(|scene, width, height, scale| {
+			func.paint(&mut VelloScenePainter::new(scene), width, height, scale)
 		})
 	}
 
-	pub fn render(&mut self, func: impl Fn(&mut Scene, u32, u32, f32)) -> anyhow::Result<()> {
+	/// Renders a frame built directly into the vello scene, for callers that splice in
+	/// prerecorded scene fragments.
+	pub fn render_scene(
+		&mut self,
+		func: impl FnOnce(&mut Scene, u32, u32, f32),
+	) -> anyhow::Result<()> {
 		let texture = self
 			.surface
 			.get_current_texture()
@@ -90,17 +700,22 @@ impl CanvasVelloScene {
 			.create_view(&TextureViewDescriptor::default());
 
 		let params = RenderParams {
-			antialiasing_method: AaConfig::Msaa16,
-			base_color: palette::css::WHITE,
+			antialiasing_method: self.options.antialiasing.into(),
+			base_color: self.options.background_color(),
 			width: self.width,
 			height: self.height,
 		};
 
 		func(&mut self.scene, self.width, self.height, self.scale);
 
-		self.renderer
-			.render_to_texture(&self.device, &self.queue, &self.scene, &view, &params)
-			.context("failed to render")?;
+		match &mut self.premultiply {
+			Some(blit) => {
+				let source = blit.source(&self.gpu.0.device, self.width, self.height);
+				self.gpu.render_to_texture(&self.scene, &source, &params)?;
+				blit.draw(&self.gpu.0.device, &self.gpu.0.queue, &view);
+			}
+			None => self.gpu.render_to_texture(&self.scene, &view, &params)?,
+		}
 
 		self.scene.reset();
 
@@ -108,4 +723,176 @@ impl CanvasVelloScene {
 
 		Ok(())
 	}
+
+	pub async fn render_to_rgba(
+		&mut self,
+		width: u32,
+		height: u32,
+		scale: f32,
+		func: &impl CanvasPaint,
+	) -> Result<Vec<u8>> {
+		let device = &self.gpu.0.device;
+		check_image_size(width, height, device.limits().max_texture_dimension_2d)?;
+		let size = Extent3d {
+			width,
+			height,
+			depth_or_array_layers: 1,
+		};
+		let texture = device.create_texture(&TextureDescriptor {
+			label: Some("blitz offscreen"),
+			size,
+			mip_level_count: 1,
+			sample_count: 1,
+			dimension: TextureDimension::D2,
+			format: TextureFormat::Rgba8Unorm,
+			usage: TextureUsages::STORAGE_BINDING | TextureUsages::COPY_SRC,
+			view_formats: &[],
+		});
+		let view = texture.create_view(&TextureViewDescriptor::default());
+
+		let params = RenderParams {
+			antialiasing_method: self.options.antialiasing.into(),
+			base_color: self.options.background_color(),
+			width,
+			height,
+		};
+
+		func.paint(
+			&mut VelloScenePainter::new(&mut self.scene),
+			width,
+			height,
+			scale,
+		);
+
+		let result = self.gpu.render_to_texture(&self.scene, &view, &params);
+		self.scene.reset();
+		result?;
+
+		// rows in a texture -> buffer copy must be padded to 256 bytes
+		let row = width as usize * 4;
+		let padded_row = row.next_multiple_of(COPY_BYTES_PER_ROW_ALIGNMENT as usize);
+		let buffer = device.create_buffer(&BufferDescriptor {
+			label: Some("blitz offscreen readback"),
+			size: (padded_row * height as usize) as u64,
+			usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+			mapped_at_creation: false,
+		});
+
+		let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+			label: Some("blitz offscreen readback"),
+		});
+		encoder.copy_texture_to_buffer(
+			texture.as_image_copy(),
+			TexelCopyBufferInfo {
+				buffer: &buffer,
+				layout: TexelCopyBufferLayout {
+					offset: 0,
+					bytes_per_row: Some(padded_row as u32),
+					rows_per_image: None,
+				},
+			},
+			size,
+		);
+		self.gpu.0.queue.submit([encoder.finish()]);
+
+		let (tx, rx) = oneshot::channel();
+		buffer.slice(..).map_async(MapMode::Read, move |result| {
+			let _ = tx.send(result);
+		});
+		rx.await
+			.context("buffer mapping was dropped")?
+			.map_err(|x| anyhow!("failed to map buffer: {x:?}"))?;
+
+		let mut out = Vec::with_capacity(row * height as usize);
+		{
+			let range = buffer.slice(..).get_mapped_range();
+			for chunk in range.chunks_exact(padded_row) {
+				out.extend_from_slice(&chunk[..row]);
+			}
+		}
+		buffer.unmap();
+
+		Ok(out)
+	}
+}
+
+fn get_2d_context(canvas: &OffscreenCanvas) -> Result<OffscreenCanvasRenderingContext2d> {
+	canvas
+		.get_context("2d")
+		.map_err(|x| anyhow!("failed to get 2d context: {x:?}"))?
+		.context("canvas already has a non-2d context")?
+		.dyn_into::<OffscreenCanvasRenderingContext2d>()
+		.map_err(|x| anyhow!("invalid 2d context: {x:?}"))
+}
+
+pub struct CanvasCpuScene {
+	renderer: VelloCpuImageRenderer,
+	context: OffscreenCanvasRenderingContext2d,
+	buffer: Vec<u8>,
+	options: BlitzRendererOptions,
+	width: u32,
+	height: u32,
+	scale: f32,
+}
+impl CanvasCpuScene {
+	pub fn new(
+		canvas: OffscreenCanvas,
+		scale: f32,
+		options: BlitzRendererOptions,
+	) -> Result<CanvasCpuScene> {
+		let width = canvas.width();
+		let height = canvas.height();
+
+		let context = get_2d_context(&canvas)?;
+
+		Ok(Self {
+			renderer: VelloCpuImageRenderer::new(width, height),
+			context,
+			buffer: vec![0; width as usize * height as usize * 4],
+			options,
+			width,
+			height,
+			scale,
+		})
+	}
+
+	fn resize(&mut self, canvas: OffscreenCanvas, scale: f32) -> Result<()> {
+		check_canvas_size(&canvas)?;
+		if canvas != self.context.canvas() {
+			self.context = get_2d_context(&canvas)?;
+		}
+
+		self.width = canvas.width();
+		self.height = canvas.height();
+		self.scale = scale;
+		// the cpu renderer is cheap to create, unlike its gpu counterpart
+		self.renderer = VelloCpuImageRenderer::new(self.width, self.height);
+		self.buffer
+			.resize(self.width as usize * self.height as usize * 4, 0);
+
+		Ok(())
+	}
+
+	pub fn render(&mut self, func: &impl CanvasPaint) -> anyhow::Result<()> {
+		let (width, height, scale) = (self.width, self.height, self.scale);
+		let background = self.options.background_color();
+
+		self.renderer.render(
+			|scene| {
+				paint_background(scene, width, height, background);
+				func.paint(scene, width, height, scale);
+			},
+			&mut self.buffer,
+		);
+		unpremultiply(&mut self.buffer);
+
+		let image =
+			ImageData::new_with_u8_clamped_array_and_sh(Clamped(&self.buffer), width, height)
+				.map_err(|x| anyhow!("failed to create image data: {x:?}"))?;
+		self.context
+			.put_image_data(&image, 0.0, 0.0)
+			.map_err(|x| anyhow!("failed to blit to canvas: {x:?}"))?;
+
+		Ok(())
+	}
 }
@@ -0,0 +1,512 @@
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::{Context, Result, bail};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use blitz_traits::net::Bytes;
+use encoding_rs::{Encoding, UTF_8};
+use miniz_oxide::{
+	DataFormat, MZFlush, MZStatus,
+	inflate::{
+		decompress_to_vec, decompress_to_vec_zlib,
+		stream::{InflateState, inflate},
+	},
+};
+use wasm_bindgen::{JsError, prelude::wasm_bindgen};
+
+use crate::{anyhow_to_obj, blitz_net::FetchedResponse};
+
+/// Redirects followed inside an archive before giving up.
+const MAX_REDIRECTS: usize = 10;
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+	haystack
+		.get(from..)?
+		.windows(needle.len())
+		.position(|x| x == needle)
+		.map(|x| x + from)
+}
+
+/// Splits a header block off the start of `data`, unfolding continuation lines and lowercasing
+/// names. Returns the headers and whatever follows the blank line.
+fn split_headers(data: &[u8]) -> (Vec<(String, String)>, &[u8]) {
+	let end = [&b"\r\n\r\n"[..], b"\n\n"]
+		.iter()
+		.filter_map(|x| find(data, x, 0).map(|pos| (pos, pos + x.len())))
+		.min();
+	let (head, rest) = match end {
+		Some((head, rest)) => (&data[..head], &data[rest..]),
+		None => (data, &data[data.len()..]),
+	};
+
+	let mut headers: Vec<(String, String)> = Vec::new();
+	for line in String::from_utf8_lossy(head).lines() {
+		if line.starts_with([' ', '\t']) {
+			if let Some((_, value)) = headers.last_mut() {
+				value.push(' ');
+				value.push_str(line.trim());
+			}
+		} else if let Some((name, value)) = line.split_once(':') {
+			headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+		}
+	}
+	(headers, rest)
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+	headers
+		.iter()
+		.find(|(key, _)| key == name)
+		.map(|(_, val)| val.as_str())
+}
+
+/// Reads a parameter like `boundary` out of a header value like `multipart/related; ...`.
+fn param(value: &str, name: &str) -> Option<String> {
+	value.split(';').skip(1).find_map(|x| {
+		let (key, val) = x.split_once('=')?;
+		key.trim()
+			.eq_ignore_ascii_case(name)
+			.then(|| val.trim().trim_matches('"').to_string())
+	})
+}
+
+/// Finds `charset=` in the first 1024 bytes of a page, which covers both `<meta charset>` and
+/// `<meta http-equiv="Content-Type">`.
+fn meta_charset(body: &[u8]) -> Option<String> {
+	let head = String::from_utf8_lossy(&body[..body.len().min(1024)]).to_ascii_lowercase();
+	let start = head.find("charset=")? + "charset=".len();
+	let value = head[start..].trim_start_matches(['"', '\'']);
+	let end = value
+		.find(|x: char| !(x.is_ascii_alphanumeric() || "-_:.".contains(x)))
+		.unwrap_or(value.len());
+	Some(value[..end].to_string())
+}
+
+/// Decodes an archived page with the charset its `Content-Type` or a `<meta>` near its start
+/// declares, or UTF-8 if neither does. A byte order mark beats both, like in browsers.
+pub(crate) fn decode_html(response: &FetchedResponse) -> String {
+	let encoding = header(&response.headers, "content-type")
+		.and_then(|x| param(x, "charset"))
+		.or_else(|| meta_charset(&response.body))
+		.and_then(|x| Encoding::for_label(x.as_bytes()))
+		.unwrap_or(UTF_8);
+	encoding.decode(&response.body).0.into_owned()
+}
+
+/// Resolves `url` against `base` and drops its fragment, so lookups match what blitz asks for.
+fn normalize_url(url: &str, base: Option<&str>) -> String {
+	let parsed = match base {
+		Some(base) => web_sys::Url::new_with_base(url, base),
+		None => web_sys::Url::new(url),
+	};
+	match parsed {
+		Ok(parsed) => {
+			parsed.set_hash("");
+			parsed.href()
+		}
+		Err(_) => url.to_string(),
+	}
+}
+
+fn decode_quoted_printable(data: &[u8]) -> Vec<u8> {
+	let hex = |x: u8| (x as char).to_digit(16).map(|x| x as u8);
+
+	let mut out = Vec::with_capacity(data.len());
+	let mut i = 0;
+	while i < data.len() {
+		if data[i] != b'=' {
+			out.push(data[i]);
+			i += 1;
+			continue;
+		}
+		match (data.get(i + 1), data.get(i + 2)) {
+			// soft line breaks
+			(Some(b'\r'), Some(b'\n')) => i += 3,
+			(Some(b'\n'), _) => i += 2,
+			(Some(a), Some(b)) if hex(*a).is_some() && hex(*b).is_some() => {
+				out.push(hex(*a).unwrap() << 4 | hex(*b).unwrap());
+				i += 3;
+			}
+			_ => {
+				out.push(b'=');
+				i += 1;
+			}
+		}
+	}
+	out
+}
+
+/// Decompresses one or more concatenated gzip members.
+fn gunzip(data: &[u8]) -> Result<Vec<u8>> {
+	const FEXTRA: u8 = 4;
+	const FNAME: u8 = 8;
+	const FCOMMENT: u8 = 16;
+	const FHCRC: u8 = 2;
+
+	let mut out = Vec::new();
+	let mut pos = 0;
+	while pos < data.len() {
+		let header = data.get(pos..pos + 10).context("truncated gzip header")?;
+		if header[..3] != [0x1f, 0x8b, 8] {
+			bail!("not a gzip stream");
+		}
+		let flags = header[3];
+		pos += 10;
+		if flags & FEXTRA != 0 {
+			let len = data.get(pos..pos + 2).context("truncated gzip header")?;
+			pos += 2 + u16::from_le_bytes([len[0], len[1]]) as usize;
+		}
+		for flag in [FNAME, FCOMMENT] {
+			if flags & flag != 0 {
+				pos = find(data, &[0], pos).context("truncated gzip header")? + 1;
+			}
+		}
+		if flags & FHCRC != 0 {
+			pos += 2;
+		}
+
+		let mut state = InflateState::new_boxed(DataFormat::Raw);
+		let mut buf = vec![0; 64 * 1024];
+		loop {
+			let input = data.get(pos..).context("truncated gzip stream")?;
+			let res = inflate(&mut state, input, &mut buf, MZFlush::None);
+			pos += res.bytes_consumed;
+			out.extend_from_slice(&buf[..res.bytes_written]);
+			match res.status {
+				Ok(MZStatus::StreamEnd) => break,
+				Ok(_) if res.bytes_consumed == 0 && res.bytes_written == 0 => {
+					bail!("truncated gzip stream")
+				}
+				Ok(_) => {}
+				Err(err) => bail!("failed to inflate gzip stream: {err:?}"),
+			}
+		}
+		// crc32 and size
+		pos += 8;
+	}
+	Ok(out)
+}
+
+fn dechunk(data: &[u8]) -> Result<Vec<u8>> {
+	let mut out = Vec::new();
+	let mut pos = 0;
+	loop {
+		let line_end = find(data, b"\n", pos).context("truncated chunked body")?;
+		let line = String::from_utf8_lossy(&data[pos..line_end]);
+		let size = line.split(';').next().unwrap_or_default().trim();
+		let size = usize::from_str_radix(size, 16)
+			.with_context(|| format!("invalid chunk size {size:?}"))?;
+		pos = line_end + 1;
+		if size == 0 {
+			return Ok(out);
+		}
+		out.extend_from_slice(data.get(pos..pos + size).context("truncated chunk")?);
+		pos += size;
+		// the chunk's line break
+		while matches!(data.get(pos), Some(b'\r' | b'\n')) {
+			pos += 1;
+		}
+	}
+}
+
+/// Parses a stored HTTP response, undoing chunking and compression.
+fn parse_http_response(url: String, block: &[u8]) -> Result<FetchedResponse> {
+	let line_end = find(block, b"\n", 0).context("missing status line")?;
+	let status_line = String::from_utf8_lossy(&block[..line_end]);
+	let mut status_line = status_line.trim().splitn(3, ' ');
+	let _version = status_line.next();
+	let status = status_line
+		.next()
+		.and_then(|x| x.parse().ok())
+		.context("invalid status line")?;
+	let status_text = status_line.next().unwrap_or_default().to_string();
+
+	let (mut headers, body) = split_headers(&block[line_end + 1..]);
+	let mut body = body.to_vec();
+	if header(&headers, "transfer-encoding").is_some_and(|x| x.eq_ignore_ascii_case("chunked")) {
+		body = dechunk(&body)?;
+	}
+	match header(&headers, "content-encoding").map(str::to_ascii_lowercase) {
+		Some(enc) if enc == "gzip" || enc == "x-gzip" => body = gunzip(&body)?,
+		Some(enc) if enc == "deflate" => {
+			// servers disagree about whether deflate has a zlib wrapper
+			body = decompress_to_vec_zlib(&body)
+				.or_else(|_| decompress_to_vec(&body))
+				.map_err(|err| anyhow::anyhow!("failed to inflate {url}: {err:?}"))?;
+		}
+		Some(enc) if enc != "identity" => bail!("{url} uses unsupported content encoding {enc}"),
+		_ => {}
+	}
+	// the body is stored decoded now
+	headers.retain(|(key, _)| {
+		!matches!(
+			key.as_str(),
+			"transfer-encoding" | "content-encoding" | "content-length"
+		)
+	});
+
+	Ok(FetchedResponse {
+		url,
+		status,
+		status_text,
+		headers,
+		body: body.into(),
+		from_cache: false,
+	})
+}
+
+fn parse_mhtml(data: &[u8]) -> Result<(HashMap<String, FetchedResponse>, String)> {
+	let (headers, body) = split_headers(data);
+	let content_type = header(&headers, "content-type").context("missing content type")?;
+	let boundary = param(content_type, "boundary").context("missing multipart boundary")?;
+	let start = param(content_type, "start");
+	let delimiter = format!("--{boundary}");
+
+	let mut resources = HashMap::new();
+	let mut main = None;
+	let mut first = None;
+
+	// delimiters only count at the start of a line
+	let mut pos = find(body, delimiter.as_bytes(), 0).context("no multipart parts")?;
+	loop {
+		let after = pos + delimiter.len();
+		if body.get(after..after + 2) == Some(b"--") {
+			break;
+		}
+		let Some(part_start) = find(body, b"\n", after).map(|x| x + 1) else {
+			break;
+		};
+		let next = find(body, format!("\n{delimiter}").as_bytes(), part_start);
+		let part_end = next.unwrap_or(body.len());
+		let mut part = &body[part_start..part_end];
+		if part.ends_with(b"\r") {
+			part = &part[..part.len() - 1];
+		}
+
+		let (part_headers, content) = split_headers(part);
+		let content = match header(&part_headers, "content-transfer-encoding")
+			.map(str::to_ascii_lowercase)
+			.as_deref()
+		{
+			Some("base64") => {
+				let content = content
+					.iter()
+					.copied()
+					.filter(|x| !x.is_ascii_whitespace())
+					.collect::<Vec<_>>();
+				BASE64_STANDARD.decode(content)?
+			}
+			Some("quoted-printable") => decode_quoted_printable(content),
+			_ => content.to_vec(),
+		};
+
+		let location = header(&part_headers, "content-location").map(|x| normalize_url(x, None));
+		let cid = header(&part_headers, "content-id")
+			.map(|x| x.trim_start_matches('<').trim_end_matches('>').to_string());
+		if let Some(url) = location
+			.clone()
+			.or_else(|| cid.as_ref().map(|x| format!("cid:{x}")))
+		{
+			let response = FetchedResponse {
+				url: url.clone(),
+				status: 200,
+				status_text: "OK".to_string(),
+				headers: part_headers
+					.iter()
+					.filter(|(key, _)| key == "content-type")
+					.cloned()
+					.collect(),
+				body: content.into(),
+				from_cache: false,
+			};
+			if let Some(cid) = &cid {
+				if start.as_deref() == Some(&format!("<{cid}>")) {
+					main = Some(url.clone());
+				}
+				resources.insert(format!("cid:{cid}"), response.clone());
+			}
+			first.get_or_insert(url.clone());
+			resources.insert(url, response);
+		}
+
+		match next {
+			Some(next) => pos = next + 1,
+			None => break,
+		}
+	}
+
+	let main = header(&headers, "snapshot-content-location")
+		.map(|x| normalize_url(x, None))
+		.or(main)
+		.or(first)
+		.context("the archive has no resources")?;
+	Ok((resources, main))
+}
+
+fn parse_warc(data: &[u8]) -> Result<(HashMap<String, FetchedResponse>, String)> {
+	let data = if data.starts_with(&[0x1f, 0x8b]) {
+		gunzip(data)?
+	} else {
+		data.to_vec()
+	};
+
+	let mut resources = HashMap::new();
+	let mut by_digest: HashMap<String, FetchedResponse> = HashMap::new();
+	let mut main = None;
+
+	let mut pos = 0;
+	while let Some(start) = find(&data, b"WARC/", pos) {
+		let (headers, rest) = split_headers(&data[start..]);
+		let block_start = data.len() - rest.len();
+		let len: usize = header(&headers, "content-length")
+			.and_then(|x| x.parse().ok())
+			.context("WARC record without a content length")?;
+		let block_end = block_start
+			.checked_add(len)
+			.context("WARC record length overflows")?;
+		let block = data
+			.get(block_start..block_end)
+			.context("truncated WARC record")?;
+		pos = block_end;
+
+		let Some(url) = header(&headers, "warc-target-uri") else {
+			continue;
+		};
+		let url = normalize_url(url.trim_start_matches('<').trim_end_matches('>'), None);
+		let digest = header(&headers, "warc-payload-digest").map(ToOwned::to_owned);
+
+		let response = match header(&headers, "warc-type") {
+			Some("response") => parse_http_response(url.clone(), block)?,
+			Some("resource") => FetchedResponse {
+				url: url.clone(),
+				status: 200,
+				status_text: "OK".to_string(),
+				headers: header(&headers, "content-type")
+					.map(|x| vec![("content-type".to_string(), x.to_string())])
+					.unwrap_or_default(),
+				body: Bytes::copy_from_slice(block),
+				from_cache: false,
+			},
+			// the payload was the same as an earlier record's, so only the headers were kept
+			Some("revisit") => {
+				let Some(original) = digest.as_ref().and_then(|x| by_digest.get(x)).or_else(|| {
+					let refers = header(&headers, "warc-refers-to-target-uri")?;
+					resources.get(&normalize_url(refers, None))
+				}) else {
+					continue;
+				};
+				FetchedResponse {
+					url: url.clone(),
+					..original.clone()
+				}
+			}
+			_ => continue,
+		};
+
+		if main.is_none()
+			&& response.status == 200
+			&& response.mime_type().is_some_and(|x| x.contains("html"))
+		{
+			main = Some(url.clone());
+		}
+		if let Some(digest) = digest {
+			by_digest.insert(digest, response.clone());
+		}
+		resources.insert(url, response);
+	}
+
+	let main = main.context("the archive has no HTML page")?;
+	Ok((resources, main))
+}
+
+/// A page captured as an MHTML or WARC file, with every resource in it kept in memory by URL.
+///
+/// Documents created with [`BlitzRenderer::from_archive`](crate::BlitzRenderer::from_archive)
+/// load everything from the archive and never touch the network, so they render the same way
+/// forever.
+#[wasm_bindgen]
+#[derive(Clone)]
+pub struct BlitzArchive {
+	resources: Arc<HashMap<String, FetchedResponse>>,
+	main_url: String,
+}
+
+impl BlitzArchive {
+	/// Answers a request from the archive, following redirects the archive recorded, with a
+	/// 404 if the resource isn't in it.
+	pub(crate) fn serve(&self, url: &str) -> FetchedResponse {
+		let mut url = normalize_url(url, None);
+		for _ in 0..MAX_REDIRECTS {
+			let Some(response) = self.resources.get(&url) else {
+				break;
+			};
+			match header(&response.headers, "location") {
+				Some(location) if (300..400).contains(&response.status) => {
+					url = normalize_url(location, Some(&url));
+				}
+				_ => return response.clone(),
+			}
+		}
+
+		FetchedResponse {
+			url,
+			status: 404,
+			status_text: "Not In Archive".to_string(),
+			headers: Vec::new(),
+			body: Bytes::new(),
+			from_cache: false,
+		}
+	}
+
+	/// The main page, with the URL it ended up at after redirects.
+	pub(crate) fn main_page(&self) -> FetchedResponse {
+		self.serve(&self.main_url)
+	}
+}
+
+#[wasm_bindgen]
+impl BlitzArchive {
+	/// Reads an MHTML file, as saved by Chromium's "Webpage, Single File".
+	pub fn from_mhtml(data: &[u8]) -> Result<BlitzArchive, JsError> {
+		let (resources, main_url) = parse_mhtml(data).map_err(anyhow_to_obj)?;
+		Ok(Self {
+			resources: Arc::new(resources),
+			main_url,
+		})
+	}
+
+	/// Reads a WARC file, optionally gzipped. The main page is the first HTML response in it.
+	pub fn from_warc(data: &[u8]) -> Result<BlitzArchive, JsError> {
+		let (resources, main_url) = parse_warc(data).map_err(anyhow_to_obj)?;
+		Ok(Self {
+			resources: Arc::new(resources),
+			main_url,
+		})
+	}
+
+	#[wasm_bindgen(getter)]
+	pub fn main_url(&self) -> String {
+		self.main_url.clone()
+	}
+
+	/// Picks another page in the archive to open.
+	#[wasm_bindgen(setter)]
+	pub fn set_main_url(&mut self, url: &str) -> Result<(), JsError> {
+		let url = normalize_url(url, None);
+		if !self.resources.contains_key(&url) {
+			return Err(JsError::new(&format!("{url} is not in the archive")));
+		}
+		self.main_url = url;
+		Ok(())
+	}
+
+	pub fn urls(&self) -> Vec<String> {
+		let mut urls = self.resources.keys().cloned().collect::<Vec<_>>();
+		urls.sort();
+		urls
+	}
+
+	pub fn get(&self, url: &str) -> Option<Vec<u8>> {
+		let response = self.serve(url);
+		(response.status == 200).then(|| response.body.to_vec())
+	}
+}
@@ -0,0 +1,371 @@
+use anyhow::{Context, Result};
+use blitz_traits::events::UiEvent;
+use vello::{
+	Scene,
+	kurbo::{Affine, Point, RoundedRect, Shape},
+	peniko::{Fill, Mix},
+};
+use wasm_bindgen::{JsError, prelude::wasm_bindgen};
+use web_sys::OffscreenCanvas;
+
+use crate::{
+	anyhow_to_obj,
+	anyrender::VelloScenePainter,
+	canvas::{BlitzGpuContext, BlitzRendererOptions, CanvasVelloScene},
+	document::{BlitzDocument, BlitzRendererEvent},
+};
+
+struct CompositorLayer {
+	id: u32,
+	/// Position and size in CSS pixels of the canvas.
+	x: f64,
+	y: f64,
+	width: f64,
+	height: f64,
+	z: i32,
+	opacity: f32,
+	corner_radius: f64,
+	/// Applied in CSS pixels around the layer's top left corner.
+	transform: Affine,
+	/// The document's last frame, in device pixels of the layer.
+	fragment: Scene,
+	stale: bool,
+}
+
+impl CompositorLayer {
+	fn clip(&self, scale: f64) -> RoundedRect {
+		RoundedRect::new(
+			0.0,
+			0.0,
+			self.width * scale,
+			self.height * scale,
+			self.corner_radius * scale,
+		)
+	}
+
+	/// Maps the layer's device pixels onto the canvas' device pixels.
+	fn device_transform(&self, scale: f64) -> Affine {
+		Affine::scale(scale)
+			* Affine::translate((self.x, self.y))
+			* self.transform
+			* Affine::scale(scale.recip())
+	}
+
+	/// Maps a point in CSS pixels of the canvas into CSS pixels of the layer.
+	fn to_local(&self, point: Point) -> Point {
+		(Affine::translate((self.x, self.y)) * self.transform).inverse() * point
+	}
+
+	fn hit(&self, point: Point) -> bool {
+		self.opacity > 0.0 && self.clip(1.0).contains(self.to_local(point))
+	}
+}
+
+/// An input event routed to the layer under the pointer, with coordinates relative to that
+/// layer's document.
+#[wasm_bindgen]
+pub struct BlitzCompositedEvent {
+	#[wasm_bindgen(readonly)]
+	pub layer: u32,
+	event: BlitzRendererEvent,
+}
+
+#[wasm_bindgen]
+impl BlitzCompositedEvent {
+	pub fn into_event(self) -> BlitzRendererEvent {
+		self.event
+	}
+}
+
+/// Draws several documents into one canvas, each in its own rectangle with its own z-order,
+/// opacity, clip and transform.
+///
+/// Documents are painted into their layer with [`BlitzCompositor::paint_layer`] and then drawn
+/// together with [`BlitzCompositor::present`]. Compositing splices recorded vello scenes, so it
+/// needs the gpu backend.
+#[wasm_bindgen]
+pub struct BlitzCompositor {
+	scene: CanvasVelloScene,
+	/// Sorted by z-order, then by creation.
+	layers: Vec<CompositorLayer>,
+	next_id: u32,
+	/// The layer that got the last pointerdown, which keeps receiving pointer events until the
+	/// matching pointerup, and keyboard events after it.
+	captured: Option<u32>,
+	focused: Option<u32>,
+	dirty: bool,
+}
+
+impl BlitzCompositor {
+	/// Looks up a layer to change, which also means the canvas needs to be redrawn.
+	fn layer(&mut self, id: u32) -> Result<&mut CompositorLayer> {
+		self.dirty = true;
+		self.layers
+			.iter_mut()
+			.find(|x| x.id == id)
+			.with_context(|| format!("no layer with id {id}"))
+	}
+
+	fn sort(&mut self) {
+		self.layers.sort_by_key(|x| (x.z, x.id));
+	}
+
+	fn _paint_layer(&mut self, id: u32, doc: &mut BlitzDocument) -> Result<bool> {
+		let scale = self.scene.scale();
+		let layer = self
+			.layers
+			.iter_mut()
+			.find(|x| x.id == id)
+			.with_context(|| format!("no layer with id {id}"))?;
+		let size = (
+			(layer.width * scale as f64).round() as u32,
+			(layer.height * scale as f64).round() as u32,
+		);
+
+		let viewport = doc.doc().viewport();
+		if viewport.window_size != size || viewport.scale() != scale {
+			let mut viewport = doc.viewport();
+			viewport.window_size = size;
+			viewport.set_hidpi_scale(scale);
+		}
+
+		if !doc.take_needs_paint() && !layer.stale {
+			return Ok(false);
+		}
+
+		layer.fragment.reset();
+		blitz_paint::paint_scene(
+			&mut VelloScenePainter::new(&mut layer.fragment),
+			doc.doc(),
+			scale as f64,
+			size.0,
+			size.1,
+			0,
+			0,
+		);
+		layer.stale = false;
+		self.dirty = true;
+		Ok(true)
+	}
+
+	fn _present(&mut self) -> Result<bool> {
+		if !self.dirty {
+			return Ok(false);
+		}
+
+		let layers = &self.layers;
+		self.scene.render_scene(|scene, _, _, scale| {
+			let scale = scale as f64;
+			for layer in layers {
+				let transform = layer.device_transform(scale);
+				scene.push_layer(
+					Fill::NonZero,
+					Mix::Normal,
+					layer.opacity,
+					transform,
+					&layer.clip(scale),
+				);
+				scene.append(&layer.fragment, Some(transform));
+				scene.pop_layer();
+			}
+		})?;
+
+		self.dirty = false;
+		Ok(true)
+	}
+
+	fn _route_event(&mut self, mut event: BlitzRendererEvent) -> Option<BlitzCompositedEvent> {
+		let ui_event = event.ui_event();
+		let is_down = matches!(ui_event, UiEvent::PointerDown(_));
+		let is_up = matches!(ui_event, UiEvent::PointerUp(_));
+
+		let Some(coords) = event.coords_mut() else {
+			// keyboard and ime events go to the last layer that was clicked
+			let layer = self.focused?;
+			return Some(BlitzCompositedEvent { layer, event });
+		};
+
+		let point = Point::new(coords.client_x as f64, coords.client_y as f64);
+		let layer = match self.captured {
+			Some(id) => self.layers.iter().find(|x| x.id == id),
+			None => self.layers.iter().rev().find(|x| x.hit(point)),
+		}?;
+
+		let local = layer.to_local(point);
+		let (dx, dy) = (
+			local.x as f32 - coords.client_x,
+			local.y as f32 - coords.client_y,
+		);
+		coords.client_x += dx;
+		coords.client_y += dy;
+		coords.page_x += dx;
+		coords.page_y += dy;
+
+		let id = layer.id;
+		if is_down {
+			self.captured = Some(id);
+			self.focused = Some(id);
+		} else if is_up {
+			self.captured = None;
+		}
+
+		Some(BlitzCompositedEvent { layer: id, event })
+	}
+}
+
+#[wasm_bindgen]
+impl BlitzCompositor {
+	/// Creates a compositor drawing into `canvas` at the given hidpi scale.
+	#[wasm_bindgen(constructor)]
+	pub fn new(
+		gpu: &BlitzGpuContext,
+		canvas: OffscreenCanvas,
+		scale: f32,
+		options: Option<BlitzRendererOptions>,
+	) -> Result<BlitzCompositor, JsError> {
+		Ok(Self {
+			scene: CanvasVelloScene::new(gpu.clone(), canvas, scale, options.unwrap_or_default())
+				.map_err(anyhow_to_obj)?,
+			layers: Vec::new(),
+			next_id: 0,
+			captured: None,
+			focused: None,
+			dirty: true,
+		})
+	}
+
+	#[wasm_bindgen]
+	pub fn resize(&mut self, canvas: OffscreenCanvas, scale: f32) -> Result<(), JsError> {
+		self.scene.resize(canvas, scale).map_err(anyhow_to_obj)?;
+		for layer in &mut self.layers {
+			layer.stale = true;
+		}
+		self.dirty = true;
+		Ok(())
+	}
+
+	#[wasm_bindgen(getter)]
+	pub fn options(&self) -> BlitzRendererOptions {
+		self.scene.options()
+	}
+
+	#[wasm_bindgen(setter)]
+	pub fn set_options(&mut self, options: &BlitzRendererOptions) {
+		self.scene.set_options(*options);
+		self.dirty = true;
+	}
+
+	/// Adds a layer at the given rectangle in CSS pixels, returning its id. Layers with a higher
+	/// `z` are drawn on top, and ties are broken by creation order.
+	#[wasm_bindgen]
+	pub fn add_layer(&mut self, x: f64, y: f64, width: f64, height: f64, z: i32) -> u32 {
+		let id = self.next_id;
+		self.next_id += 1;
+
+		self.layers.push(CompositorLayer {
+			id,
+			x,
+			y,
+			width,
+			height,
+			z,
+			opacity: 1.0,
+			corner_radius: 0.0,
+			transform: Affine::IDENTITY,
+			fragment: Scene::new(),
+			stale: true,
+		});
+		self.sort();
+		self.dirty = true;
+		id
+	}
+
+	#[wasm_bindgen]
+	pub fn remove_layer(&mut self, id: u32) {
+		self.layers.retain(|x| x.id != id);
+		if self.captured == Some(id) {
+			self.captured = None;
+		}
+		if self.focused == Some(id) {
+			self.focused = None;
+		}
+		self.dirty = true;
+	}
+
+	#[wasm_bindgen]
+	pub fn set_layer_rect(
+		&mut self,
+		id: u32,
+		x: f64,
+		y: f64,
+		width: f64,
+		height: f64,
+	) -> Result<(), JsError> {
+		let layer = self.layer(id).map_err(anyhow_to_obj)?;
+		if layer.width != width || layer.height != height {
+			layer.stale = true;
+		}
+		layer.x = x;
+		layer.y = y;
+		layer.width = width;
+		layer.height = height;
+		Ok(())
+	}
+
+	#[wasm_bindgen]
+	pub fn set_layer_z(&mut self, id: u32, z: i32) -> Result<(), JsError> {
+		self.layer(id).map_err(anyhow_to_obj)?.z = z;
+		self.sort();
+		Ok(())
+	}
+
+	#[wasm_bindgen]
+	pub fn set_layer_opacity(&mut self, id: u32, opacity: f32) -> Result<(), JsError> {
+		self.layer(id).map_err(anyhow_to_obj)?.opacity = opacity.clamp(0.0, 1.0);
+		Ok(())
+	}
+
+	#[wasm_bindgen]
+	pub fn set_layer_corner_radius(&mut self, id: u32, radius: f64) -> Result<(), JsError> {
+		self.layer(id).map_err(anyhow_to_obj)?.corner_radius = radius.max(0.0);
+		Ok(())
+	}
+
+	/// Sets a 2d transform `[a, b, c, d, e, f]` like `DOMMatrix`'s, applied in CSS pixels around
+	/// the layer's top left corner.
+	#[wasm_bindgen]
+	#[allow(clippy::too_many_arguments)]
+	pub fn set_layer_transform(
+		&mut self,
+		id: u32,
+		a: f64,
+		b: f64,
+		c: f64,
+		d: f64,
+		e: f64,
+		f: f64,
+	) -> Result<(), JsError> {
+		self.layer(id).map_err(anyhow_to_obj)?.transform = Affine::new([a, b, c, d, e, f]);
+		Ok(())
+	}
+
+	/// Sizes the document to its layer and records a new frame of it if it changed, returning
+	/// whether it did. Call after [`BlitzDocument::resolve`].
+	#[wasm_bindgen]
+	pub fn paint_layer(&mut self, id: u32, doc: &mut BlitzDocument) -> Result<bool, JsError> {
+		self._paint_layer(id, doc).map_err(anyhow_to_obj)
+	}
+
+	/// Draws every layer into the canvas if anything changed, returning whether it did.
+	#[wasm_bindgen]
+	pub fn present(&mut self) -> Result<bool, JsError> {
+		self._present().map_err(anyhow_to_obj)
+	}
+
+	/// Finds the layer an event belongs to, the topmost one under the pointer or the focused one
+	/// for keyboard events. The event must have been created relative to the canvas.
+	#[wasm_bindgen]
+	pub fn route_event(&mut self, event: BlitzRendererEvent) -> Option<BlitzCompositedEvent> {
+		self._route_event(event)
+	}
+}
@@ -0,0 +1,459 @@
+use std::{
+	net::{Ipv4Addr, Ipv6Addr},
+	sync::Mutex,
+};
+
+use anyhow::{Context, Result};
+use serde_json::{Value, json};
+use web_sys::Url;
+
+/// Keeps the jar from growing without bound, the oldest cookies go first.
+const MAX_COOKIES: usize = 3000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SameSite {
+	Strict,
+	Lax,
+	None,
+	/// Treated like `Lax`, as browsers do now.
+	Unset,
+}
+
+impl SameSite {
+	fn parse(value: &str) -> Self {
+		match value.to_ascii_lowercase().as_str() {
+			"strict" => Self::Strict,
+			"lax" => Self::Lax,
+			"none" => Self::None,
+			_ => Self::Unset,
+		}
+	}
+
+	fn as_str(self) -> &'static str {
+		match self {
+			Self::Strict => "strict",
+			Self::Lax => "lax",
+			Self::None => "none",
+			Self::Unset => "unset",
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+struct Cookie {
+	name: String,
+	value: String,
+	/// Lowercased, without a leading dot.
+	domain: String,
+	/// Set without a `Domain`, so it only goes to exactly `domain` and not its subdomains.
+	host_only: bool,
+	path: String,
+	/// Milliseconds since the epoch, or `None` for a cookie that lasts as long as the jar.
+	expires: Option<f64>,
+	secure: bool,
+	http_only: bool,
+	same_site: SameSite,
+	/// Milliseconds since the epoch, which orders cookies with paths of the same length.
+	created: f64,
+}
+
+impl Cookie {
+	fn expired(&self, now: f64) -> bool {
+		self.expires.is_some_and(|x| x <= now)
+	}
+
+	fn same_key(&self, other: &Cookie) -> bool {
+		self.name == other.name && self.domain == other.domain && self.path == other.path
+	}
+
+	fn to_json(&self) -> Value {
+		json!({
+			"name": self.name,
+			"value": self.value,
+			"domain": self.domain,
+			"hostOnly": self.host_only,
+			"path": self.path,
+			"expires": self.expires,
+			"secure": self.secure,
+			"httpOnly": self.http_only,
+			"sameSite": self.same_site.as_str(),
+			"created": self.created,
+		})
+	}
+
+	fn from_json(value: &Value) -> Option<Self> {
+		let string = |key: &str| value.get(key)?.as_str().map(ToOwned::to_owned);
+		let flag = |key: &str| value.get(key).and_then(Value::as_bool).unwrap_or_default();
+		Some(Self {
+			name: string("name")?,
+			value: string("value")?,
+			domain: string("domain")?
+				.trim_start_matches('.')
+				.to_ascii_lowercase(),
+			host_only: flag("hostOnly"),
+			path: string("path").filter(|x| x.starts_with('/'))?,
+			expires: value.get("expires").and_then(Value::as_f64),
+			secure: flag("secure"),
+			http_only: flag("httpOnly"),
+			same_site: SameSite::parse(&string("sameSite").unwrap_or_default()),
+			created: value
+				.get("created")
+				.and_then(Value::as_f64)
+				.unwrap_or_else(now_ms),
+		})
+	}
+}
+
+fn now_ms() -> f64 {
+	js_sys::Date::now()
+}
+
+/// The parts of a URL cookies care about.
+struct CookieUrl {
+	secure: bool,
+	host: String,
+	path: String,
+}
+
+impl CookieUrl {
+	/// Only http and https URLs have cookies.
+	fn parse(url: &str) -> Option<Self> {
+		let url = Url::new(url).ok()?;
+		let protocol = url.protocol();
+		if protocol != "http:" && protocol != "https:" {
+			return None;
+		}
+		let host = url.hostname().to_ascii_lowercase();
+		// localhost is a secure context even over http
+		let secure = protocol == "https:" || host == "localhost" || host == "127.0.0.1";
+		Some(Self {
+			secure,
+			host,
+			path: url.pathname(),
+		})
+	}
+
+	/// The directory of the path, for cookies set without a `Path`.
+	fn default_path(&self) -> String {
+		match self.path.rfind('/') {
+			Some(0) | None => "/".to_string(),
+			Some(end) => self.path[..end].to_string(),
+		}
+	}
+
+	/// The registrable domain of the host, or the whole host if it's an IP or a public suffix.
+	/// Schemeful, so http and https are different sites.
+	fn site(&self) -> (bool, &str) {
+		if is_ip(&self.host) {
+			return (self.secure, &self.host);
+		}
+		(
+			self.secure,
+			psl::domain_str(&self.host).unwrap_or(&self.host),
+		)
+	}
+}
+
+/// Whether `domain` is a public suffix like `com` or `github.io`. Unlisted top level domains
+/// count as well.
+fn is_public_suffix(domain: &str) -> bool {
+	psl::suffix_str(domain) == Some(domain)
+}
+
+fn is_ip(host: &str) -> bool {
+	host.parse::<Ipv4Addr>().is_ok()
+		|| host
+			.trim_start_matches('[')
+			.trim_end_matches(']')
+			.parse::<Ipv6Addr>()
+			.is_ok()
+}
+
+fn domain_match(host: &str, domain: &str) -> bool {
+	host == domain || (!is_ip(host) && host.strip_suffix(domain).is_some_and(|x| x.ends_with('.')))
+}
+
+fn path_match(path: &str, cookie_path: &str) -> bool {
+	path == cookie_path
+		|| path
+			.strip_prefix(cookie_path)
+			.is_some_and(|rest| cookie_path.ends_with('/') || rest.starts_with('/'))
+}
+
+/// Whether a request to `url` made by a document at `site` is same-site. Requests that no
+/// document made, like top level loads, are.
+fn same_site(url: &CookieUrl, site: Option<&str>) -> bool {
+	site.and_then(CookieUrl::parse)
+		.is_none_or(|site| site.site() == url.site())
+}
+
+/// Parses a `Set-Cookie` header or a `document.cookie` assignment, following RFC 6265 section
+/// 5.2.
+fn parse(header: &str, url: &CookieUrl) -> Option<Cookie> {
+	let (pair, attrs) = header.split_once(';').unwrap_or((header, ""));
+	let (name, value) = match pair.split_once('=') {
+		Some((name, value)) => (name.trim(), value.trim()),
+		// a cookie without a name, as browsers accept
+		None => ("", pair.trim()),
+	};
+	if name.is_empty() && value.is_empty() {
+		return None;
+	}
+
+	let now = now_ms();
+	let mut cookie = Cookie {
+		name: name.to_string(),
+		value: value.to_string(),
+		domain: String::new(),
+		host_only: true,
+		path: url.default_path(),
+		expires: None,
+		secure: false,
+		http_only: false,
+		same_site: SameSite::Unset,
+		created: now,
+	};
+	let mut domain = None;
+	let mut max_age = None;
+
+	for attr in attrs.split(';') {
+		let (key, value) = attr.split_once('=').unwrap_or((attr, ""));
+		let value = value.trim();
+		match key.trim().to_ascii_lowercase().as_str() {
+			"expires" => {
+				let time = js_sys::Date::parse(value);
+				if !time.is_nan() {
+					cookie.expires = Some(time);
+				}
+			}
+			"max-age" => {
+				let digits = value.strip_prefix('-').unwrap_or(value);
+				if !digits.is_empty() && digits.bytes().all(|x| x.is_ascii_digit()) {
+					let seconds = value.parse::<f64>().unwrap_or(f64::MAX);
+					max_age = Some(if seconds <= 0.0 {
+						f64::MIN
+					} else {
+						now + seconds * 1000.0
+					});
+				}
+			}
+			"domain" if !value.is_empty() => {
+				domain = Some(value.trim_start_matches('.').to_ascii_lowercase());
+			}
+			"path" => {
+				if value.starts_with('/') {
+					cookie.path = value.to_string();
+				}
+			}
+			"secure" => cookie.secure = true,
+			"httponly" => cookie.http_only = true,
+			"samesite" => cookie.same_site = SameSite::parse(value),
+			_ => {}
+		}
+	}
+	// Max-Age wins over Expires wherever it is
+	if max_age.is_some() {
+		cookie.expires = max_age;
+	}
+
+	match domain {
+		// a public suffix would match every site under it, so it's only allowed as the host
+		// itself, which makes the cookie host-only
+		Some(domain) if domain == url.host && is_public_suffix(&domain) => cookie.domain = domain,
+		Some(domain) => {
+			if is_public_suffix(&domain) || !domain_match(&url.host, &domain) {
+				return None;
+			}
+			cookie.domain = domain;
+			cookie.host_only = false;
+		}
+		None => cookie.domain = url.host.clone(),
+	}
+
+	Some(cookie)
+}
+
+/// An RFC 6265 cookie store, shared by every document of a provider.
+#[derive(Default)]
+pub struct CookieJar {
+	cookies: Mutex<Vec<Cookie>>,
+}
+
+impl CookieJar {
+	/// Stores a cookie set for `url`, by a response when `http` or by a script otherwise.
+	fn store(&self, url: &CookieUrl, header: &str, http: bool, same_site: bool) {
+		let Some(mut cookie) = parse(header, url) else {
+			return;
+		};
+
+		if cookie.secure && !url.secure {
+			return;
+		}
+		if cookie.http_only && !http {
+			return;
+		}
+		// the browser defaults, so cross-site frames can't plant cookies for the page
+		if cookie.same_site == SameSite::None && !cookie.secure {
+			return;
+		}
+		if cookie.same_site != SameSite::None && !same_site {
+			return;
+		}
+		if cookie.name.starts_with("__Secure-") && !cookie.secure {
+			return;
+		}
+		if cookie.name.starts_with("__Host-")
+			&& (!cookie.secure || !cookie.host_only || cookie.path != "/")
+		{
+			return;
+		}
+
+		let mut cookies = self.cookies.lock().unwrap();
+		let now = now_ms();
+		cookies.retain(|x| !x.expired(now));
+
+		if let Some(index) = cookies.iter().position(|x| x.same_key(&cookie)) {
+			let old = &cookies[index];
+			if old.http_only && !http {
+				return;
+			}
+			cookie.created = old.created;
+			cookies.remove(index);
+		} else if !url.secure
+			&& cookies.iter().any(|x| {
+				x.secure
+					&& x.name == cookie.name
+					&& (domain_match(&x.domain, &cookie.domain)
+						|| domain_match(&cookie.domain, &x.domain))
+					&& path_match(&cookie.path, &x.path)
+			}) {
+			// insecure sites can't shadow secure cookies
+			return;
+		}
+
+		if cookie.expired(now) {
+			return;
+		}
+		cookies.push(cookie);
+		if cookies.len() > MAX_COOKIES {
+			let oldest = cookies
+				.iter()
+				.enumerate()
+				.min_by(|a, b| a.1.created.total_cmp(&b.1.created))
+				.map(|x| x.0);
+			if let Some(oldest) = oldest {
+				cookies.remove(oldest);
+			}
+		}
+	}
+
+	/// The cookies to send to `url`, most specific path first.
+	fn matching(&self, url: &CookieUrl, http: bool, same_site: bool) -> String {
+		let mut cookies = self.cookies.lock().unwrap();
+		let now = now_ms();
+		cookies.retain(|x| !x.expired(now));
+
+		let mut matching = cookies
+			.iter()
+			.filter(|x| {
+				if x.host_only {
+					url.host == x.domain
+				} else {
+					domain_match(&url.host, &x.domain)
+				}
+			})
+			.filter(|x| path_match(&url.path, &x.path))
+			.filter(|x| !x.secure || url.secure)
+			.filter(|x| !x.http_only || http)
+			.filter(|x| same_site || x.same_site == SameSite::None)
+			.collect::<Vec<_>>();
+		matching.sort_by(|a, b| {
+			b.path
+				.len()
+				.cmp(&a.path.len())
+				.then(a.created.total_cmp(&b.created))
+		});
+
+		matching
+			.into_iter()
+			.map(|x| {
+				if x.name.is_empty() {
+					x.value.clone()
+				} else {
+					format!("{}={}", x.name, x.value)
+				}
+			})
+			.collect::<Vec<_>>()
+			.join("; ")
+	}
+
+	/// The `Cookie` header for a request to `url` made by the document at `site`.
+	pub fn header(&self, url: &str, site: Option<&str>) -> Option<String> {
+		let url = CookieUrl::parse(url)?;
+		let same_site = same_site(&url, site);
+		Some(self.matching(&url, true, same_site)).filter(|x| !x.is_empty())
+	}
+
+	/// Stores the cookies of `Set-Cookie` headers from a response for `url` to a request made
+	/// by the document at `site`.
+	pub fn set_from_response<'a>(
+		&self,
+		url: &str,
+		site: Option<&str>,
+		headers: impl IntoIterator<Item = &'a str>,
+	) {
+		let Some(url) = CookieUrl::parse(url) else {
+			return;
+		};
+		let same_site = same_site(&url, site);
+		for header in headers {
+			self.store(&url, header, true, same_site);
+		}
+	}
+
+	/// `document.cookie` for a document at `url`, leaving out HttpOnly cookies.
+	pub fn document_cookie(&self, url: &str) -> String {
+		CookieUrl::parse(url)
+			.map(|url| self.matching(&url, false, true))
+			.unwrap_or_default()
+	}
+
+	/// Assigns to `document.cookie` for a document at `url`.
+	pub fn set_document_cookie(&self, url: &str, cookie: &str) {
+		if let Some(url) = CookieUrl::parse(url) {
+			self.store(&url, cookie, false, true);
+		}
+	}
+
+	/// Every cookie that hasn't expired as a JSON array, including HttpOnly ones.
+	pub fn export(&self) -> String {
+		let mut cookies = self.cookies.lock().unwrap();
+		let now = now_ms();
+		cookies.retain(|x| !x.expired(now));
+		Value::Array(cookies.iter().map(Cookie::to_json).collect()).to_string()
+	}
+
+	/// Adds cookies from [`CookieJar::export`], replacing ones with the same name, domain and
+	/// path. Entries that are malformed or expired are skipped. Returns how many were added.
+	pub fn import(&self, json: &str) -> Result<usize> {
+		let value: Value = serde_json::from_str(json).context("invalid cookie json")?;
+		let entries = value.as_array().context("expected an array of cookies")?;
+
+		let mut cookies = self.cookies.lock().unwrap();
+		let now = now_ms();
+		let mut added = 0;
+		for cookie in entries.iter().filter_map(Cookie::from_json) {
+			if cookie.expired(now) {
+				continue;
+			}
+			cookies.retain(|x| !x.same_key(&cookie));
+			cookies.push(cookie);
+			added += 1;
+		}
+		Ok(added)
+	}
+
+	pub fn clear(&self) {
+		self.cookies.lock().unwrap().clear();
+	}
+}
@@ -9,21 +9,63 @@ use blitz_traits::{
 	},
 	shell::Viewport,
 };
-use js_sys::Function;
+use js_sys::{Function, Promise};
 use keyboard_types::{Code, Key, Location, Modifiers};
 use std::{
 	any::Any,
 	collections::HashMap,
 	mem::transmute,
 	ops::{Deref, DerefMut},
+	path::PathBuf,
 	str::FromStr,
+	sync::{
+		Arc,
+		atomic::{AtomicBool, Ordering},
+	},
 };
 use wasm_bindgen::{JsError, JsValue, prelude::wasm_bindgen};
-use web_sys::{Event as JsEvent, KeyboardEvent, PointerEvent, WheelEvent, console};
+use web_sys::{
+	DataTransfer, DragEvent, DragEventInit, Event as JsEvent, File, KeyboardEvent, PointerEvent,
+	WheelEvent, console,
+};
+
+use crate::{
+	anyhow_to_obj,
+	blitz_net::{
+		BlitzNetRequest, BlitzRequestDestination, BlitzRequestOutcome, NetProgress, Provider,
+	},
+	cache::{BlitzCacheLimits, BlitzCacheStorage},
+	har::to_har,
+	print::{BlitzPrintOptions, print_to_pdf},
+	scheme::BlitzSchemeRegistry,
+	screenshot::{ScreenshotRect, screenshot_png, screenshot_svg},
+	single_file::save_single_file,
+};
 
 #[wasm_bindgen]
 pub struct BlitzNode(pub usize);
 
+fn is_file_input(node: &Node) -> bool {
+	node.element_data()
+		.is_some_and(|x| &*x.name.local == "input")
+		&& node
+			.attr("type".into())
+			.is_some_and(|x| x.eq_ignore_ascii_case("file"))
+}
+
+/// A `drop` event carrying `files`, for listeners in the document.
+fn drop_event(files: &[File]) -> Result<DragEvent, JsValue> {
+	let transfer = DataTransfer::new()?;
+	for file in files {
+		transfer.items().add_with_file(file)?;
+	}
+	let init = DragEventInit::new();
+	init.set_bubbles(true);
+	init.set_cancelable(true);
+	init.set_data_transfer(Some(&transfer));
+	DragEvent::new_with_event_init_dict("drop", &init)
+}
+
 #[wasm_bindgen]
 impl BlitzNode {
 	pub fn new(doc: &mut BlitzDocument, name: String) -> Self {
@@ -139,6 +181,15 @@ impl BlitzNode {
 	pub fn get_debug_string(&self, doc: &mut BlitzDocument) -> Result<String, JsError> {
 		Ok(format!("{:#?}", doc.node(self)?))
 	}
+
+	pub fn screenshot_png(&self, doc: &mut BlitzDocument) -> Result<Vec<u8>, JsError> {
+		let rect = ScreenshotRect::node(&doc.inner, self.0).map_err(anyhow_to_obj)?;
+		screenshot_png(&mut doc.inner, rect).map_err(anyhow_to_obj)
+	}
+	pub fn export_svg(&self, doc: &mut BlitzDocument) -> Result<String, JsError> {
+		let rect = ScreenshotRect::node(&doc.inner, self.0).map_err(anyhow_to_obj)?;
+		screenshot_svg(&mut doc.inner, rect).map_err(anyhow_to_obj)
+	}
 }
 
 impl From<&Node> for BlitzNode {
@@ -150,6 +201,8 @@ impl From<&Node> for BlitzNode {
 #[wasm_bindgen]
 pub struct BlitzEventHandler {
 	listeners: HashMap<(usize, u8), Vec<Function>>,
+	/// Listeners for events blitz-dom has no event kinds for, like `load` and `drop`.
+	custom_listeners: HashMap<(usize, &'static str), Vec<Function>>,
 	temp_override: Option<Function>,
 }
 #[wasm_bindgen]
@@ -162,6 +215,7 @@ impl BlitzEventHandler {
 	pub fn new() -> Self {
 		Self {
 			listeners: HashMap::new(),
+			custom_listeners: HashMap::new(),
 			temp_override: None,
 		}
 	}
@@ -172,6 +226,14 @@ impl BlitzEventHandler {
 		event_kind: &str,
 		func: Function,
 	) -> Result<(), JsError> {
+		if let Some(kind) = Self::str_to_custom_kind(event_kind) {
+			self.custom_listeners
+				.entry((node, kind))
+				.or_default()
+				.push(func);
+			return Ok(());
+		}
+
 		let kind =
 			Self::str_to_kind(event_kind).ok_or_else(|| JsError::new("Invalid event kind"))?;
 		self.listeners
@@ -187,6 +249,13 @@ impl BlitzEventHandler {
 		event_kind: &str,
 		func: Function,
 	) -> Result<(), JsError> {
+		if let Some(kind) = Self::str_to_custom_kind(event_kind) {
+			self.custom_listeners
+				.entry((node, kind))
+				.and_modify(|x| x.retain(|x| *x != func));
+			return Ok(());
+		}
+
 		let kind =
 			Self::str_to_kind(event_kind).ok_or_else(|| JsError::new("Invalid event kind"))?;
 		self.listeners
@@ -195,6 +264,35 @@ impl BlitzEventHandler {
 		Ok(())
 	}
 
+	fn str_to_custom_kind(s: &str) -> Option<&'static str> {
+		["load", "error", "drop", "change"]
+			.into_iter()
+			.find(|x| *x == s)
+	}
+
+	/// Calls the `kind` listeners of each node in `chain` with `event`, until one stops
+	/// propagation. Returns whether one prevented the default.
+	fn dispatch_custom_event(&self, chain: &[usize], kind: &'static str, event: &JsEvent) -> bool {
+		for node in chain {
+			let Some(listeners) = self.custom_listeners.get(&(*node, kind)) else {
+				continue;
+			};
+			for listener in listeners {
+				if let Err(err) = listener.call1(&JsValue::NULL, event) {
+					console::warn_3(
+						&"error while calling event listener for ".into(),
+						&kind.into(),
+						&err,
+					);
+				}
+			}
+			if event.cancel_bubble() {
+				break;
+			}
+		}
+		event.default_prevented()
+	}
+
 	fn str_to_kind(s: &str) -> Option<DomEventKind> {
 		match s {
 			"pointermove" => Some(DomEventKind::PointerMove),
@@ -338,6 +436,22 @@ impl EventHandler for &mut BlitzEventHandler {
 #[wasm_bindgen]
 pub struct BlitzRendererEvent(UiEvent);
 
+impl BlitzRendererEvent {
+	pub(crate) fn ui_event(&self) -> &UiEvent {
+		&self.0
+	}
+
+	pub(crate) fn coords_mut(&mut self) -> Option<&mut PointerCoords> {
+		match &mut self.0 {
+			UiEvent::PointerMove(x) | UiEvent::PointerUp(x) | UiEvent::PointerDown(x) => {
+				Some(&mut x.coords)
+			}
+			UiEvent::Wheel(x) => Some(&mut x.coords),
+			_ => None,
+		}
+	}
+}
+
 enum BlitzDocumentInner {
 	Owned(HtmlDocument),
 	Ref(&'static mut HtmlDocument),
@@ -361,66 +475,604 @@ impl DerefMut for BlitzDocumentInner {
 }
 
 #[wasm_bindgen]
-pub struct BlitzDocument(BlitzDocumentInner);
+pub struct BlitzDocument {
+	inner: BlitzDocumentInner,
+	/// Set whenever something may have changed what the next frame looks like, including by the
+	/// shell provider when blitz-dom asks for a redraw.
+	dirty: Arc<AtomicBool>,
+	needs_paint: bool,
+	blink_phase: u64,
+	/// The provider the document fetches with, absent for borrowed documents.
+	net: Option<Arc<Provider>>,
+}
+
+impl Drop for BlitzDocument {
+	fn drop(&mut self) {
+		// nothing is left to hand the responses to
+		self.cancel_all_requests();
+		if let Some(net) = &self.net {
+			net.forget_document(self.inner.id());
+		}
+	}
+}
 
 impl BlitzDocument {
-	pub fn new(doc: HtmlDocument) -> Self {
-		Self(BlitzDocumentInner::Owned(doc))
+	pub fn new(doc: HtmlDocument, dirty: Arc<AtomicBool>, net: Arc<Provider>) -> Self {
+		dirty.store(true, Ordering::Relaxed);
+		net.set_document_url(doc.id(), doc.resolve_url("").to_string());
+		Self {
+			inner: BlitzDocumentInner::Owned(doc),
+			dirty,
+			needs_paint: true,
+			blink_phase: 0,
+			net: Some(net),
+		}
 	}
 	pub fn unsafe_with_ref(doc: &mut HtmlDocument) -> Self {
-		Self(BlitzDocumentInner::Ref(unsafe { transmute(doc) }))
+		// the owning document is damaged by the event dispatch that lends it out, so changes
+		// made through this one are still picked up
+		Self {
+			inner: BlitzDocumentInner::Ref(unsafe { transmute(doc) }),
+			dirty: Arc::new(AtomicBool::new(false)),
+			needs_paint: false,
+			blink_phase: 0,
+			net: None,
+		}
+	}
+
+	/// Loading progress of the document's network provider.
+	pub fn net_progress(&self) -> Option<NetProgress> {
+		self.net.as_ref().map(|x| x.progress())
+	}
+
+	/// Marks the document as needing a new frame.
+	pub fn damage(&self) {
+		self.dirty.store(true, Ordering::Relaxed);
+	}
+
+	/// Returns whether the last resolve produced a frame that hasn't been painted yet.
+	pub fn take_needs_paint(&mut self) -> bool {
+		std::mem::take(&mut self.needs_paint)
+	}
+
+	/// Runs `dispatch` with the document lent to listeners through the overrider, like during ui
+	/// events.
+	fn with_overrider<R>(
+		&mut self,
+		events: &BlitzEventHandler,
+		dispatch: impl FnOnce(&BlitzEventHandler) -> R,
+	) -> R {
+		let temp_override_ret = events.temp_override.as_ref().map(|func| {
+			(
+				func,
+				func.call1(
+					&JsValue::NULL,
+					&BlitzDocument::unsafe_with_ref(&mut *self.inner).into(),
+				)
+				.unwrap(),
+			)
+		});
+
+		let ret = dispatch(events);
+
+		if let Some((func, ret)) = temp_override_ret {
+			func.call1(&JsValue::NULL, &ret).unwrap();
+		}
+		self.damage();
+		ret
+	}
+
+	/// `id` and its ancestors, nearest first.
+	fn ancestors(&self, id: usize) -> Vec<usize> {
+		let mut chain = vec![id];
+		while let Some(parent) = chain
+			.last()
+			.and_then(|x| self.inner.get_node(*x))
+			.and_then(|x| x.parent)
+		{
+			chain.push(parent);
+		}
+		chain
+	}
+
+	/// Selects `paths` in the file input `id`, keeping only the first unless it's `multiple`,
+	/// and fires `change` listeners.
+	fn select_files(&mut self, events: &BlitzEventHandler, id: usize, mut paths: Vec<PathBuf>) {
+		let Some(node) = self.inner.get_node_mut(id) else {
+			return;
+		};
+		if node.attr("multiple".into()).is_none() {
+			paths.truncate(1);
+		}
+		let Some(files) = node.element_data_mut().and_then(|x| x.file_data_mut()) else {
+			return;
+		};
+		files.clear();
+		files.extend(paths);
+
+		let chain = self.ancestors(id);
+		self.with_overrider(events, |events| match JsEvent::new("change") {
+			Ok(event) => {
+				events.dispatch_custom_event(&chain, "change", &event);
+			}
+			Err(err) => {
+				console::warn_3(
+					&"failed to instantiate js event for".into(),
+					&"change".into(),
+					&err,
+				);
+			}
+		});
+	}
+
+	/// Text carets blink, so a focused text input needs a frame every half second.
+	fn caret_blinked(&mut self, time: f64) -> bool {
+		let has_caret = self
+			.inner
+			.get_focussed_node_id()
+			.and_then(|x| self.inner.get_node(x))
+			.and_then(|x| x.element_data())
+			.is_some_and(|x| x.text_input_data().is_some());
+		if !has_caret {
+			return false;
+		}
+
+		let phase = (time * 2.0) as u64;
+		phase != std::mem::replace(&mut self.blink_phase, phase)
 	}
 
 	pub fn doc(&self) -> &HtmlDocument {
-		&self.0
+		&self.inner
 	}
 	pub fn node(&self, node: &BlitzNode) -> Result<&Node, JsError> {
-		self.0
+		self.inner
 			.get_node(node.0)
 			.ok_or_else(|| JsError::new("invalid node"))
 	}
 
 	pub fn viewport(&mut self) -> impl DerefMut<Target = Viewport> {
-		self.0.viewport_mut()
+		self.damage();
+		self.inner.viewport_mut()
 	}
 
 	pub fn mutator(&mut self) -> DocumentMutator<'_> {
-		DocumentMutator::new(&mut self.0)
+		self.damage();
+		DocumentMutator::new(&mut self.inner)
 	}
 }
 
 #[wasm_bindgen]
 impl BlitzDocument {
-	pub fn resolve(&mut self, time: f64) {
-		self.0.resolve(time);
+	/// Returns whether anything may have changed since the last frame, without resolving.
+	pub fn needs_frame(&self) -> bool {
+		self.dirty.load(Ordering::Relaxed)
+			|| self.inner.is_animating()
+			|| self.net.as_ref().is_some_and(|x| x.idle_pending())
+	}
+
+	/// Resolves style and layout if anything changed, returning whether a new frame is needed.
+	pub fn resolve(&mut self, time: f64) -> bool {
+		let blinked = self.caret_blinked(time);
+		if !self.needs_frame() && !blinked {
+			return false;
+		}
+
+		self.dirty.store(false, Ordering::Relaxed);
+		self.inner.resolve(time);
+		self.needs_paint = true;
+		self.promote_visible_images();
+
+		// resolving handles finished loads, which can start new ones, so only now is it known
+		// whether the network is really idle
+		if let Some(net) = &self.net {
+			net.poll_idle();
+		}
+		true
+	}
+
+	/// Lets queued requests for images in the viewport go before the other images.
+	fn promote_visible_images(&self) {
+		let Some(net) = &self.net else {
+			return;
+		};
+		let queued = net.queued_urls(self.inner.id(), BlitzRequestDestination::Image);
+		if queued.is_empty() {
+			return;
+		}
+		let Ok(images) = self.inner.query_selector_all("img[src]") else {
+			return;
+		};
+
+		let viewport = self.inner.viewport();
+		let scroll = self.inner.viewport_scroll();
+		let top = scroll.y as f32;
+		let bottom = top + viewport.window_size.1 as f32 / viewport.scale();
+
+		for id in images {
+			let Some(node) = self.inner.get_node(id) else {
+				continue;
+			};
+			let Some(src) = node.attr("src".into()) else {
+				continue;
+			};
+			let url = self.inner.resolve_url(src);
+			if !queued.iter().any(|x| x == url.as_str()) {
+				continue;
+			}
+
+			let y = node.absolute_position(0.0, 0.0).y;
+			if y < bottom && y + node.final_layout.size.height >= top {
+				net.promote(self.inner.id(), url.as_str());
+			}
+		}
+	}
+
+	/// Fires `load` and `error` listeners on the nodes whose requests the document handled since
+	/// the last call, like `img` elements, stylesheets and the stylesheets fonts were loaded
+	/// from. Call once per frame after resolving.
+	pub fn dispatch_net_events(&mut self, events: &BlitzEventHandler) {
+		let Some(net) = &self.net else {
+			return;
+		};
+		let finished = net.take_events(self.inner.id());
+		if finished.is_empty() {
+			return;
+		}
+
+		let mut targets = Vec::new();
+		for event in finished {
+			let Some(node) = self.inner.get_node(event.node_id) else {
+				continue;
+			};
+			// resolving applied the response, so an image without data failed to decode
+			let decoded = event.destination != BlitzRequestDestination::Image
+				|| node.element_data().and_then(|x| x.image_data()).is_some();
+			targets.push((event.node_id, !(event.ok && decoded)));
+		}
+		if targets.is_empty() {
+			return;
+		}
+
+		self.with_overrider(events, |events| {
+			for (node, error) in targets {
+				let kind = if error { "error" } else { "load" };
+				match JsEvent::new(kind) {
+					Ok(event) => {
+						events.dispatch_custom_event(&[node], kind, &event);
+					}
+					Err(err) => {
+						console::warn_3(
+							&"failed to instantiate js event for".into(),
+							&kind.into(),
+							&err,
+						);
+					}
+				}
+			}
+		});
+	}
+
+	/// Gives files picked since the last call to the file inputs they were picked for and fires
+	/// their `change` listeners. Call once per frame after resolving.
+	///
+	/// File pickers that answer asynchronously can't hand their files to blitz-dom when it opens
+	/// them, so they're applied here instead.
+	pub fn apply_picked_files(&mut self, events: &BlitzEventHandler) {
+		let Some(net) = &self.net else {
+			return;
+		};
+		for (target, paths) in net.files().take_picked() {
+			if self.inner.get_node(target).is_some_and(is_file_input) {
+				self.select_files(events, target, paths);
+			} else {
+				console::warn_1(&"files were picked for a file input that's gone".into());
+			}
+		}
+	}
+
+	/// The file input `event` would open a file picker for, and what it has selected. Clicks go
+	/// to the input under the pointer, keys to the focused one.
+	fn file_dialog_target(&self, event: &UiEvent) -> Option<(usize, Vec<PathBuf>)> {
+		let id = match event {
+			UiEvent::PointerUp(x) => self.inner.hit(x.coords.page_x, x.coords.page_y)?.node_id,
+			_ => self.inner.get_focussed_node_id()?,
+		};
+		let id = self
+			.ancestors(id)
+			.into_iter()
+			.find(|x| self.inner.get_node(*x).is_some_and(is_file_input))?;
+		let selected = self
+			.inner
+			.get_node(id)?
+			.element_data()?
+			.file_data()?
+			.iter()
+			.cloned()
+			.collect();
+		Some((id, selected))
+	}
+
+	/// Handles files dropped at `x`, `y` on the canvas, in the same coordinates as pointer
+	/// events. `drop` listeners are fired on the element there and its ancestors, then unless one
+	/// prevented the default, the files are selected in the nearest file input among them.
+	///
+	/// Returns whether the drop was taken by a listener or an input.
+	pub fn drop_files(
+		&mut self,
+		events: &BlitzEventHandler,
+		files: Vec<File>,
+		x: f32,
+		y: f32,
+	) -> bool {
+		let Some(hit) = self.inner.hit(x, y) else {
+			return false;
+		};
+		let chain = self.ancestors(hit.node_id);
+
+		match drop_event(&files) {
+			Ok(event) => {
+				let prevented = self.with_overrider(events, |events| {
+					events.dispatch_custom_event(&chain, "drop", &event)
+				});
+				if prevented {
+					return true;
+				}
+			}
+			Err(err) => {
+				console::warn_3(
+					&"failed to instantiate js event for".into(),
+					&"drop".into(),
+					&err,
+				);
+			}
+		}
+
+		let (Some(target), Some(net)) = (
+			chain
+				.iter()
+				.copied()
+				.find(|x| self.inner.get_node(*x).is_some_and(is_file_input)),
+			&self.net,
+		) else {
+			return false;
+		};
+		let store = net.files();
+		let paths = files.into_iter().map(|x| store.insert(x)).collect();
+		self.select_files(events, target, paths);
+		true
+	}
+
+	/// Calls `callback` with a [`BlitzNetError`](crate::blitz_net::BlitzNetError) and the node
+	/// the request was made for, if any, whenever one of the document's requests fails, instead
+	/// of logging it.
+	pub fn set_net_error_callback(&self, callback: Option<Function>) {
+		if let Some(net) = &self.net {
+			net.set_error_callback(callback);
+		}
+	}
+
+	/// Whether any of the document's requests are in flight.
+	#[wasm_bindgen(getter)]
+	pub fn loading(&self) -> bool {
+		self.net.as_ref().is_some_and(|x| !x.is_idle())
+	}
+
+	/// Requests made by the document that haven't finished yet.
+	pub fn pending_requests(&self) -> Vec<BlitzNetRequest> {
+		let mut requests = self.requests();
+		requests.retain(|x| x.outcome == BlitzRequestOutcome::Pending);
+		requests
+	}
+
+	/// Every request made by the document since the log was last cleared, oldest first. Only
+	/// the last thousand finished requests of all documents are kept.
+	pub fn requests(&self) -> Vec<BlitzNetRequest> {
+		self.net
+			.as_ref()
+			.map(|x| x.requests(self.inner.id()))
+			.unwrap_or_default()
+	}
+
+	/// Exports the request log as a HAR 1.2 document for loading into HAR viewers.
+	pub fn export_har(&self) -> String {
+		to_har(&self.requests())
+	}
+
+	pub fn clear_requests(&self) {
+		if let Some(net) = &self.net {
+			net.clear_requests();
+		}
+	}
+
+	/// Cancels one of the document's pending requests by its id, returning whether it was still
+	/// pending. blitz-dom doesn't say when the node that made a request goes away, so this is
+	/// up to the host.
+	pub fn cancel_request(&self, id: u32) -> bool {
+		self.net
+			.as_ref()
+			.is_some_and(|x| x.cancel_request(self.inner.id(), id))
+	}
+
+	/// Cancels every pending request the document made, returning how many there were.
+	pub fn cancel_all_requests(&self) -> u32 {
+		self.net
+			.as_ref()
+			.map_or(0, |x| x.cancel_requests(self.inner.id()))
+	}
+
+	/// How many requests may talk to the same origin at once, with the rest queued by
+	/// destination so stylesheets and fonts go before images.
+	#[wasm_bindgen(getter)]
+	pub fn max_connections_per_origin(&self) -> usize {
+		self.net.as_ref().map_or(0, |x| x.max_per_origin())
+	}
+
+	#[wasm_bindgen(setter)]
+	pub fn set_max_connections_per_origin(&self, max: usize) {
+		if let Some(net) = &self.net {
+			net.set_max_per_origin(max);
+		}
+	}
+
+	/// The custom scheme handlers the document's requests go through.
+	#[wasm_bindgen(getter)]
+	pub fn schemes(&self) -> Option<BlitzSchemeRegistry> {
+		self.net.as_ref().map(|x| x.schemes().clone())
+	}
+
+	/// The cookies the page would see in `document.cookie`, leaving out HttpOnly ones.
+	pub fn cookies(&self) -> String {
+		self.net.as_ref().map_or_else(String::new, |x| {
+			x.cookies()
+				.document_cookie(self.inner.resolve_url("").as_str())
+		})
+	}
+
+	/// Sets a cookie like assigning `cookie` to `document.cookie` would.
+	pub fn set_cookie(&self, cookie: &str) {
+		if let Some(net) = &self.net {
+			net.cookies()
+				.set_document_cookie(self.inner.resolve_url("").as_str(), cookie);
+		}
+	}
+
+	/// Every cookie in the jar as JSON, including HttpOnly and session ones, for the host to
+	/// persist and pass to [`BlitzDocument::import_cookies`] later.
+	pub fn export_cookies(&self) -> String {
+		self.net
+			.as_ref()
+			.map_or_else(|| "[]".to_string(), |x| x.cookies().export())
+	}
+
+	/// Adds cookies exported by [`BlitzDocument::export_cookies`], returning how many there were.
+	pub fn import_cookies(&self, json: &str) -> Result<usize, JsError> {
+		let Some(net) = &self.net else {
+			return Ok(0);
+		};
+		net.cookies().import(json).map_err(anyhow_to_obj)
+	}
+
+	pub fn clear_cookies(&self) {
+		if let Some(net) = &self.net {
+			net.cookies().clear();
+		}
+	}
+
+	/// Forgets every response in the HTTP cache, including the ones in the host's storage.
+	pub fn clear_cache(&self) {
+		if let Some(net) = &self.net {
+			net.cache().clear();
+		}
+	}
+
+	/// Size of the in-memory HTTP cache in bytes.
+	pub fn cache_size(&self) -> usize {
+		self.net.as_ref().map_or(0, |x| x.cache().size())
+	}
+
+	pub fn cache_limits(&self) -> Option<BlitzCacheLimits> {
+		self.net.as_ref().map(|x| x.cache().limits())
+	}
+
+	pub fn set_cache_limits(&self, limits: &BlitzCacheLimits) {
+		if let Some(net) = &self.net {
+			net.cache().set_limits(*limits);
+		}
+	}
+
+	/// Keeps cached responses in `storage` as well as in memory, so they outlive the page.
+	pub fn set_cache_storage(&self, storage: Option<BlitzCacheStorage>) {
+		if let Some(net) = &self.net {
+			net.cache().set_storage(storage);
+		}
+	}
+
+	/// Calls `callback` every time the document finishes loading everything it requested.
+	pub fn set_network_idle_callback(&self, callback: Option<Function>) {
+		if let Some(net) = &self.net {
+			net.set_idle_callback(callback);
+		}
+	}
+
+	/// Resolves once nothing is loading, after the document has handled every response.
+	pub fn wait_for_network_idle(&self) -> Promise {
+		match &self.net {
+			Some(net) => net.wait_for_idle(),
+			None => Promise::resolve(&JsValue::UNDEFINED),
+		}
 	}
 
 	pub fn root(&self) -> BlitzNode {
-		self.0.root_node().into()
+		self.inner.root_node().into()
 	}
 	pub fn focused(&self) -> Option<BlitzNode> {
-		self.0.get_focussed_node_id().map(BlitzNode)
+		self.inner.get_focussed_node_id().map(BlitzNode)
 	}
 
 	pub fn query_selector(&self, selector: &str) -> Result<Option<BlitzNode>, JsError> {
-		self.0
+		self.inner
 			.query_selector(selector)
 			.map(|x| x.map(BlitzNode))
 			.map_err(|_| JsError::new("selector failed to parse"))
 	}
 
 	pub fn add_style(&mut self, css: &str) {
-		self.0.add_user_agent_stylesheet(css);
-	} 
+		self.damage();
+		self.inner.add_user_agent_stylesheet(css);
+	}
+
+	pub fn screenshot_png(&mut self) -> Result<Vec<u8>, JsError> {
+		let rect = ScreenshotRect::page(&self.inner);
+		screenshot_png(&mut self.inner, rect).map_err(anyhow_to_obj)
+	}
+	pub fn export_svg(&mut self) -> Result<String, JsError> {
+		let rect = ScreenshotRect::page(&self.inner);
+		screenshot_svg(&mut self.inner, rect).map_err(anyhow_to_obj)
+	}
+
+	pub fn print_to_pdf(&mut self, options: &BlitzPrintOptions) -> Result<Vec<u8>, JsError> {
+		self.damage();
+		print_to_pdf(&mut self.inner, *options).map_err(anyhow_to_obj)
+	}
+
+	/// Keeps the bodies of what the document fetches from now on, for
+	/// [`Self::save_as_single_file`]. Call it right after creating the document to keep all
+	/// of them.
+	pub fn set_keep_resources(&self, keep: bool) {
+		if let Some(net) = &self.net {
+			net.set_keep_resources(self.inner.id(), keep);
+		}
+	}
+
+	/// Serializes the document as it is now into one HTML file, with the stylesheets, fonts and
+	/// images it fetched inlined as data URLs. Only resources fetched while
+	/// [`Self::set_keep_resources`] was on are inlined, the rest are linked by absolute URL.
+	/// Form values and scroll positions are recorded in the file and restored by a script when
+	/// it's opened in a browser.
+	pub fn save_as_single_file(&self) -> String {
+		let doc_id = self.inner.id();
+		let resource = |url: &str| self.net.as_ref()?.resource(doc_id, url);
+		save_single_file(&self.inner, &resource)
+	}
 
 	pub fn toggle_devtools(&mut self) {
-		self.0.devtools_mut().toggle_highlight_hover();
-		self.0.devtools_mut().toggle_show_layout();
+		self.damage();
+		self.inner.devtools_mut().toggle_highlight_hover();
+		self.inner.devtools_mut().toggle_show_layout();
 	}
 
 	pub fn event(&mut self, events: &mut BlitzEventHandler, event: BlitzRendererEvent) {
-		let mut handler = EventDriver::new(self.0.deref_mut(), events);
+		self.damage();
+		// the shell provider needs to know which input a file picker it's asked to open is for
+		let files = self.net.as_ref().map(|x| x.files());
+		if let Some(files) = &files {
+			files.set_dialog_target(self.file_dialog_target(&event.0));
+		}
+		let mut handler = EventDriver::new(self.inner.deref_mut(), events);
 		handler.handle_ui_event(event.0);
+		if let Some(files) = files {
+			files.set_dialog_target(None);
+		}
 	}
 
 	pub fn event_pointer(
@@ -0,0 +1,163 @@
+use std::{
+	collections::HashMap,
+	path::{Path, PathBuf},
+	sync::{Arc, Mutex},
+};
+
+use js_sys::{Array, Function, Promise, Uint8Array};
+use wasm_bindgen::{JsCast, JsValue, prelude::wasm_bindgen};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Blob, File, FilePropertyBag};
+
+use crate::{blitz_net::ProviderError, bundle::mime_for};
+
+#[wasm_bindgen(typescript_custom_section)]
+const BLITZ_FILE_RESOLVER: &'static str = r#"
+type BlitzFileResolver = (path: string) => File | Blob | Uint8Array | undefined | Promise<File | Blob | Uint8Array | undefined>;
+"#;
+
+#[wasm_bindgen]
+extern "C" {
+	#[wasm_bindgen(typescript_type = "BlitzFileResolver")]
+	pub type BlitzFileResolver;
+}
+
+#[derive(Default)]
+struct FileStoreState {
+	files: HashMap<PathBuf, File>,
+	next_id: u32,
+	resolver: Option<Function>,
+	/// The file input a file picker opened now would be for, and what it has selected.
+	dialog_target: Option<(usize, Vec<PathBuf>)>,
+	/// Selections from the file picker that arrived after the dialog was opened, waiting for
+	/// the document to give them to the file input they were picked for.
+	picked: Vec<(usize, Vec<PathBuf>)>,
+}
+
+/// Files the host handed to the document's file inputs.
+///
+/// blitz-dom keeps what a file input has selected as paths, which don't mean anything in a
+/// browser, so files added here get a made up path to stand in for them. Paths the store
+/// doesn't know, like ones set through the DOM, are asked of the resolver when a form with
+/// them is submitted.
+#[wasm_bindgen]
+#[derive(Clone, Default)]
+pub struct BlitzFileStore {
+	state: Arc<Mutex<FileStoreState>>,
+}
+unsafe impl Send for BlitzFileStore {}
+unsafe impl Sync for BlitzFileStore {}
+
+impl BlitzFileStore {
+	pub fn insert(&self, file: File) -> PathBuf {
+		let mut state = self.state.lock().unwrap();
+		state.next_id += 1;
+		// the file name is the last component, which is what gets submitted
+		let path = PathBuf::from(format!("/blitz-files/{}/{}", state.next_id, file.name()));
+		state.files.insert(path.clone(), file);
+		path
+	}
+
+	/// Adds every file in an array or `FileList`, skipping anything that isn't a file.
+	pub(crate) fn insert_all(&self, files: &JsValue) -> Vec<PathBuf> {
+		let Ok(Some(iter)) = js_sys::try_iter(files) else {
+			return Vec::new();
+		};
+		iter.filter_map(Result::ok)
+			.filter_map(|x| x.dyn_into::<File>().ok())
+			.map(|x| self.insert(x))
+			.collect()
+	}
+
+	/// Set by the document around events that may open a file picker.
+	pub(crate) fn set_dialog_target(&self, target: Option<(usize, Vec<PathBuf>)>) {
+		self.state.lock().unwrap().dialog_target = target;
+	}
+
+	pub(crate) fn dialog_target(&self) -> Option<(usize, Vec<PathBuf>)> {
+		self.state.lock().unwrap().dialog_target.clone()
+	}
+
+	pub(crate) fn push_picked(&self, node_id: usize, paths: Vec<PathBuf>) {
+		self.state.lock().unwrap().picked.push((node_id, paths));
+	}
+
+	pub(crate) fn take_picked(&self) -> Vec<(usize, Vec<PathBuf>)> {
+		std::mem::take(&mut self.state.lock().unwrap().picked)
+	}
+
+	/// The contents of the file at `path` and the name to submit it with.
+	pub(crate) async fn read(&self, path: &Path) -> Result<(Blob, String), ProviderError> {
+		let name = path
+			.file_name()
+			.map(|x| x.to_string_lossy().into_owned())
+			.unwrap_or_default();
+
+		let (file, resolver) = {
+			let state = self.state.lock().unwrap();
+			(state.files.get(path).cloned(), state.resolver.clone())
+		};
+		if let Some(file) = file {
+			return Ok((file.into(), name));
+		}
+
+		let not_found = || ProviderError::File(path.display().to_string());
+		let resolver = resolver.ok_or_else(not_found)?;
+		let mut value = resolver.call1(&JsValue::NULL, &path.display().to_string().into())?;
+		if value.is_instance_of::<Promise>() {
+			value = JsFuture::from(value.unchecked_into::<Promise>()).await?;
+		}
+
+		if value.is_instance_of::<Blob>() {
+			Ok((value.unchecked_into(), name))
+		} else if value.is_instance_of::<Uint8Array>() {
+			let options = FilePropertyBag::new();
+			options.set_type(mime_for(&name));
+			let file =
+				File::new_with_u8_array_sequence_and_options(&Array::of1(&value), &name, &options)?;
+			Ok((file.into(), name))
+		} else {
+			Err(not_found())
+		}
+	}
+}
+
+#[wasm_bindgen]
+impl BlitzFileStore {
+	#[wasm_bindgen(constructor)]
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Adds a file, returning the path it stands in for.
+	pub fn add(&self, file: File) -> String {
+		self.insert(file).display().to_string()
+	}
+
+	pub fn get(&self, path: &str) -> Option<File> {
+		self.state
+			.lock()
+			.unwrap()
+			.files
+			.get(Path::new(path))
+			.cloned()
+	}
+
+	pub fn remove(&self, path: &str) -> bool {
+		self.state
+			.lock()
+			.unwrap()
+			.files
+			.remove(Path::new(path))
+			.is_some()
+	}
+
+	pub fn clear(&self) {
+		self.state.lock().unwrap().files.clear();
+	}
+
+	/// Sets the function asked for the contents of paths the store doesn't have.
+	pub fn set_resolver(&self, resolver: Option<BlitzFileResolver>) {
+		self.state.lock().unwrap().resolver = resolver.map(JsCast::unchecked_into);
+	}
+}
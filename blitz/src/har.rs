@@ -0,0 +1,96 @@
+use js_sys::Date;
+use serde_json::{Value, json};
+
+use crate::{
+	blitz_net::{BlitzNetRequest, BlitzRequestOutcome},
+	now,
+};
+
+fn name_values(pairs: &[(String, String)]) -> Value {
+	pairs
+		.iter()
+		.map(|(name, value)| json!({ "name": name, "value": value }))
+		.collect()
+}
+
+fn entry(request: &BlitzNetRequest) -> Value {
+	// unfinished requests are timed until now so stalls show up in the waterfall
+	let end = request.end_time.unwrap_or_else(now);
+	let time = ((end - request.start_time) * 1000.0).max(0.0);
+	let size = request.bytes.map_or(-1, |x| x as i64);
+	// time spent queued behind other requests to the same origin
+	let blocked = request.sent_time.map_or(0.0, |x| {
+		((x - request.start_time) * 1000.0).clamp(0.0, time)
+	});
+
+	let redirect = request
+		.response_url
+		.as_ref()
+		.filter(|x| **x != request.url)
+		.cloned()
+		.unwrap_or_default();
+
+	let outcome = match request.outcome {
+		BlitzRequestOutcome::Pending => "pending",
+		BlitzRequestOutcome::Succeeded => "succeeded",
+		BlitzRequestOutcome::Failed => "failed",
+		BlitzRequestOutcome::Cancelled => "cancelled",
+	};
+
+	json!({
+		"startedDateTime": String::from(Date::new(&request.started_at.into()).to_iso_string()),
+		"time": time,
+		"request": {
+			"method": request.method,
+			"url": request.url,
+			"httpVersion": "HTTP/1.1",
+			"cookies": [],
+			"headers": name_values(&request.request_headers),
+			"queryString": name_values(&request.query),
+			"headersSize": -1,
+			"bodySize": -1,
+		},
+		"response": {
+			// HAR uses 0 for requests without a response
+			"status": request.status.unwrap_or(0),
+			"statusText": request.status_text.as_deref().unwrap_or_default(),
+			"httpVersion": "HTTP/1.1",
+			"cookies": [],
+			"headers": name_values(&request.response_headers),
+			"content": {
+				"size": size.max(0),
+				"mimeType": request.mime_type.as_deref().unwrap_or_default(),
+			},
+			"redirectURL": redirect,
+			"headersSize": -1,
+			"bodySize": size,
+			"_error": request.error,
+		},
+		"cache": {},
+		"timings": {
+			"blocked": blocked,
+			"send": 0,
+			"wait": time - blocked,
+			"receive": 0,
+		},
+		"_destination": format!("{:?}", request.destination).to_lowercase(),
+		"_outcome": outcome,
+		"_fromCache": request.from_cache,
+	})
+}
+
+/// Serializes requests as a HAR 1.2 log.
+pub fn to_har(requests: &[BlitzNetRequest]) -> String {
+	json!({
+		"log": {
+			"version": "1.2",
+			"creator": {
+				"name": env!("CARGO_PKG_NAME"),
+				"version": env!("CARGO_PKG_VERSION"),
+			},
+			"pages": [],
+			"entries": requests.iter().map(entry).collect::<Vec<_>>(),
+		}
+	})
+	.to_string()
+}
@@ -1,25 +1,52 @@
-use std::sync::Arc;
+use std::{
+	path::PathBuf,
+	sync::{
+		Arc,
+		atomic::{AtomicBool, Ordering},
+	},
+};
 
 use anyhow::Context;
+use anyrender::PaintScene;
 use blitz_dom::{DocumentConfig, FontContext};
 use blitz_html::{HtmlDocument, HtmlProvider};
-use blitz_traits::shell::{ClipboardError, ColorScheme, ShellProvider, Viewport};
+use blitz_traits::shell::{ClipboardError, ColorScheme, FileDialogFilter, ShellProvider, Viewport};
 use fontique::Blob;
-use js_sys::{Array, Function};
-use wasm_bindgen::{JsError, JsValue, prelude::wasm_bindgen};
-use web_sys::OffscreenCanvas;
+use js_sys::{Array, Function, Promise};
+use vello::kurbo::Affine;
+use wasm_bindgen::{JsCast, JsError, JsValue, prelude::wasm_bindgen};
+use wasm_bindgen_futures::{JsFuture, spawn_local};
+use web_sys::{OffscreenCanvas, console};
 
 use crate::{
-	anyrender::VelloScenePainter,
+	anyrender::TransformedScenePainter,
 	blitz_net::{BlitzFetcherFunction, Provider as NetProvider},
-	canvas::CanvasVelloScene,
+	canvas::{
+		BlitzGpuContext, BlitzRenderBackend, BlitzRendererOptions, CanvasPaint, CanvasScene,
+		color_from_rgba,
+	},
+	capture::{BlitzArchive, decode_html},
 	document::{BlitzDocument, BlitzEventHandler},
+	files::BlitzFileStore,
+	scheme::BlitzSchemeRegistry,
 };
 
 pub mod anyrender;
 pub mod blitz_net;
+pub mod bundle;
+pub mod cache;
 pub mod canvas;
+pub mod capture;
+pub mod compositor;
+pub mod cookies;
 pub mod document;
+pub mod files;
+pub mod har;
+pub mod pdf;
+pub mod print;
+pub mod scheme;
+pub mod screenshot;
+pub mod single_file;
 
 #[wasm_bindgen(typescript_custom_section)]
 const BLITZ_RENDERER_RESULT: &'static str = r#"
@@ -41,41 +68,252 @@ fn anyhow_to_obj(val: anyhow::Error) -> JsError {
 	JsError::new(&format!("{:?}", val))
 }
 
+/// Seconds on the `performance.now()` clock, which is what frames are timed with.
+fn now() -> f64 {
+	web_sys::window()
+		.and_then(|x| x.performance())
+		.map(|x| x.now() / 1000.0)
+		.unwrap_or_default()
+}
+
+#[wasm_bindgen(typescript_custom_section)]
+const BLITZ_FILE_DIALOG_FUNCTION: &'static str = r#"
+type BlitzFileDialogFunction = (multiple: boolean, accept: string[]) => File[] | FileList | Promise<File[] | FileList>;
+"#;
+
+#[wasm_bindgen]
+extern "C" {
+	#[wasm_bindgen(typescript_type = "BlitzFileDialogFunction")]
+	pub type BlitzFileDialogFunction;
+}
+
 #[wasm_bindgen]
 pub struct BlitzShellProvider {
 	set_clipboard: Function,
+	/// Opens the browser's file picker, for clicks on file inputs.
+	open_file_dialog: Option<Function>,
+	redraw: Arc<AtomicBool>,
+	files: BlitzFileStore,
 }
 unsafe impl Send for BlitzShellProvider {}
 unsafe impl Sync for BlitzShellProvider {}
 #[wasm_bindgen]
 impl BlitzShellProvider {
+	/// `open_file_dialog` is called with whether several files may be picked and the accepted
+	/// extensions, like `.png`. Browsers only show a picker during user activation, which a
+	/// click on a file input still counts as when the document handles it on the next frame.
 	#[wasm_bindgen(constructor)]
-	pub fn new(set_clipboard: Function) -> Self {
-		Self { set_clipboard }
+	pub fn new(set_clipboard: Function, open_file_dialog: Option<BlitzFileDialogFunction>) -> Self {
+		Self {
+			set_clipboard,
+			open_file_dialog: open_file_dialog.map(JsCast::unchecked_into),
+			redraw: Arc::new(AtomicBool::new(true)),
+			files: BlitzFileStore::default(),
+		}
+	}
+
+	/// Where the files of the document's file inputs come from. The network provider reads
+	/// submitted files out of it.
+	#[wasm_bindgen(getter)]
+	pub fn files(&self) -> BlitzFileStore {
+		self.files.clone()
 	}
 }
 impl ShellProvider for BlitzShellProvider {
+	fn request_redraw(&self) {
+		self.redraw.store(true, Ordering::Relaxed);
+	}
+
 	fn set_clipboard_text(&self, text: String) -> Result<(), ClipboardError> {
 		self.set_clipboard
 			.call1(&JsValue::NULL, &text.into())
 			.map(|_| ())
 			.map_err(|_| ClipboardError)
 	}
+
+	fn open_file_dialog(
+		&self,
+		is_multiple: bool,
+		filter: Option<FileDialogFilter>,
+	) -> Vec<PathBuf> {
+		// blitz-dom replaces the input's selection with whatever is returned, so when no files
+		// are picked right away it's given back what it had
+		let (target, selected) = self.files.dialog_target().unzip();
+		let selected = selected.unwrap_or_default();
+		let Some(func) = &self.open_file_dialog else {
+			return selected;
+		};
+		let accept = filter
+			.into_iter()
+			.flat_map(|x| x.extensions)
+			.map(|x| JsValue::from(format!(".{}", x.trim_start_matches('.'))))
+			.collect::<Array>();
+
+		let picked = match func.call2(&JsValue::NULL, &is_multiple.into(), &accept) {
+			Ok(picked) => picked,
+			Err(err) => {
+				console::error_2(&"file dialog failed:".into(), &err);
+				return selected;
+			}
+		};
+		if !picked.is_instance_of::<Promise>() {
+			let paths = self.files.insert_all(&picked);
+			// an empty selection means the dialog was cancelled
+			return if paths.is_empty() { selected } else { paths };
+		}
+
+		// blitz-dom wants the files right away, so ones picked later are handed to the
+		// document by BlitzDocument::apply_picked_files
+		let files = self.files.clone();
+		let redraw = self.redraw.clone();
+		spawn_local(async move {
+			match JsFuture::from(picked.unchecked_into::<Promise>()).await {
+				Ok(picked) => {
+					let paths = files.insert_all(&picked);
+					match target {
+						_ if paths.is_empty() => {}
+						Some(target) => {
+							files.push_picked(target, paths);
+							redraw.store(true, Ordering::Relaxed);
+						}
+						None => console::warn_1(
+							&"files were picked but the file input they're for is unknown".into(),
+						),
+					}
+				}
+				Err(err) => console::error_2(&"file dialog failed:".into(), &err),
+			}
+		});
+		selected
+	}
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BlitzLoaderStyle {
+	/// An animated bar that doesn't say how far along loading is.
+	#[default]
+	Indeterminate,
+	/// A bar filled by how much of what the document requested has arrived.
+	Determinate,
+	Hidden,
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BlitzLoaderPosition {
+	#[default]
+	Top,
+	Bottom,
+}
+
+/// Appearance of the loading bar drawn while [`BlitzRenderer::render`] is told the page is
+/// loading.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlitzLoaderOptions {
+	pub style: BlitzLoaderStyle,
+	pub position: BlitzLoaderPosition,
+	/// Bar color as `0xRRGGBBAA`.
+	pub color: u32,
+	/// Color behind the bar as `0xRRGGBBAA`.
+	pub track_color: u32,
+	/// Height in CSS pixels.
+	pub height: f32,
+	/// Draw over the page instead of making room for the bar.
+	pub overlay: bool,
+}
+
+impl Default for BlitzLoaderOptions {
+	fn default() -> Self {
+		Self {
+			style: BlitzLoaderStyle::default(),
+			position: BlitzLoaderPosition::default(),
+			color: 0x6496ffff,
+			track_color: 0x00000000,
+			height: 8.0,
+			overlay: false,
+		}
+	}
+}
+
+#[wasm_bindgen]
+impl BlitzLoaderOptions {
+	/// An 8px indeterminate bar at the top of the page.
+	#[wasm_bindgen(constructor)]
+	pub fn new() -> Self {
+		Self::default()
+	}
+}
+
+struct BlitzRenderPaint<'a> {
+	doc: &'a BlitzDocument,
+	loader: &'a BlitzLoaderOptions,
+	loading: bool,
+	time: f64,
+}
+impl CanvasPaint for BlitzRenderPaint<'_> {
+	fn paint(&self, scene: &mut impl PaintScene, width: u32, height: u32, scale: f32) {
+		let loader = self.loader;
+		let bar_height = if self.loading && loader.style != BlitzLoaderStyle::Hidden {
+			((loader.height * scale) as u32).min(height)
+		} else {
+			0
+		};
+		// the page is squeezed next to the bar unless the bar overlays it
+		let reserved = if loader.overlay { 0 } else { bar_height };
+		let (offset, bar_y) = match loader.position {
+			BlitzLoaderPosition::Top => (reserved, 0),
+			BlitzLoaderPosition::Bottom => (0, height - bar_height),
+		};
+
+		blitz_paint::paint_scene(
+			scene,
+			self.doc.doc(),
+			scale as f64,
+			width,
+			height - reserved,
+			0,
+			offset,
+		);
+
+		if bar_height > 0 {
+			let fraction = (loader.style == BlitzLoaderStyle::Determinate)
+				.then(|| self.doc.net_progress().map_or(0.0, |x| x.fraction()));
+			BlitzRenderer::loader(
+				loader,
+				fraction,
+				width,
+				bar_height,
+				scale,
+				self.time,
+				&mut TransformedScenePainter::new(scene, Affine::translate((0.0, bar_y as f64))),
+			);
+		}
+	}
 }
 
 #[wasm_bindgen]
 pub struct BlitzRenderer {
-	scene: CanvasVelloScene,
+	scene: CanvasScene,
+	/// The canvas has not been painted since it was (re)created.
+	stale: bool,
+	was_loading: bool,
+	loader: BlitzLoaderOptions,
 }
 #[wasm_bindgen]
 impl BlitzRenderer {
+	#[allow(clippy::too_many_arguments)]
 	async fn _new(
 		html: String,
 		base: String,
-		fetcher: BlitzFetcherFunction,
+		net: NetProvider,
 		shell: BlitzShellProvider,
 		canvas: OffscreenCanvas,
 		scale: f32,
+		backend: BlitzRenderBackend,
+		options: BlitzRendererOptions,
+		gpu: Option<BlitzGpuContext>,
 	) -> anyhow::Result<(BlitzRenderer, BlitzDocument, BlitzEventHandler)> {
 		let mut font_ctx = FontContext::default();
 		font_ctx.collection.register_fonts(
@@ -83,6 +321,9 @@ impl BlitzRenderer {
 			None,
 		);
 
+		let redraw = shell.redraw.clone();
+		net.set_files(shell.files.clone());
+		let net = Arc::new(net);
 		let config = DocumentConfig {
 			font_ctx: Some(font_ctx),
 			viewport: Some(Viewport::new(
@@ -92,7 +333,7 @@ impl BlitzRenderer {
 				ColorScheme::Dark,
 			)),
 			base_url: Some(base),
-			net_provider: Some(Arc::new(NetProvider::new(fetcher))),
+			net_provider: Some(net.clone()),
 			shell_provider: Some(Arc::new(shell)),
 			html_parser_provider: Some(Arc::new(HtmlProvider)),
 			..Default::default()
@@ -103,15 +344,19 @@ impl BlitzRenderer {
 
 		Ok((
 			BlitzRenderer {
-				scene: CanvasVelloScene::new(canvas, scale)
+				scene: CanvasScene::new(canvas, scale, backend, options, gpu)
 					.await
-					.context("failed to create vello scene")?,
+					.context("failed to create canvas scene")?,
+				stale: true,
+				was_loading: false,
+				loader: BlitzLoaderOptions::default(),
 			},
-			BlitzDocument::new(doc),
+			BlitzDocument::new(doc, redraw, net),
 			BlitzEventHandler::new(),
 		))
 	}
 
+	#[allow(clippy::too_many_arguments)]
 	#[wasm_bindgen]
 	pub async fn new(
 		html: String,
@@ -120,47 +365,172 @@ impl BlitzRenderer {
 		shell: BlitzShellProvider,
 		canvas: OffscreenCanvas,
 		scale: f32,
+		backend: Option<BlitzRenderBackend>,
+		options: Option<BlitzRendererOptions>,
+		schemes: Option<BlitzSchemeRegistry>,
 	) -> Result<BlitzRendererResult, JsError> {
-		Self::_new(html, base, fetcher, shell, canvas, scale)
-			.await
-			.map(|x| JsValue::from(Array::of3(&x.0.into(), &x.1.into(), &x.2.into())).into())
-			.map_err(anyhow_to_obj)
+		Self::_new(
+			html,
+			base,
+			NetProvider::new(fetcher, schemes.unwrap_or_default()),
+			shell,
+			canvas,
+			scale,
+			backend.unwrap_or_default(),
+			options.unwrap_or_default(),
+			None,
+		)
+		.await
+		.map(|x| JsValue::from(Array::of3(&x.0.into(), &x.1.into(), &x.2.into())).into())
+		.map_err(anyhow_to_obj)
 	}
 
-	async fn _resize(
+	/// Like [`BlitzRenderer::new`], but renders with an existing gpu context instead of creating
+	/// a device and renderer of its own.
+	#[allow(clippy::too_many_arguments)]
+	#[wasm_bindgen]
+	pub async fn new_with_gpu(
+		html: String,
+		base: String,
+		fetcher: BlitzFetcherFunction,
+		shell: BlitzShellProvider,
+		canvas: OffscreenCanvas,
+		scale: f32,
+		gpu: &BlitzGpuContext,
+		options: Option<BlitzRendererOptions>,
+		schemes: Option<BlitzSchemeRegistry>,
+	) -> Result<BlitzRendererResult, JsError> {
+		Self::_new(
+			html,
+			base,
+			NetProvider::new(fetcher, schemes.unwrap_or_default()),
+			shell,
+			canvas,
+			scale,
+			BlitzRenderBackend::Gpu,
+			options.unwrap_or_default(),
+			Some(gpu.clone()),
+		)
+		.await
+		.map(|x| JsValue::from(Array::of3(&x.0.into(), &x.1.into(), &x.2.into())).into())
+		.map_err(anyhow_to_obj)
+	}
+
+	/// Opens the main page of a captured archive. Every request is answered from the archive,
+	/// resources that aren't in it fail, and the fetcher is never used.
+	#[wasm_bindgen]
+	pub async fn from_archive(
+		archive: &BlitzArchive,
+		shell: BlitzShellProvider,
+		canvas: OffscreenCanvas,
+		scale: f32,
+		backend: Option<BlitzRenderBackend>,
+		options: Option<BlitzRendererOptions>,
+	) -> Result<BlitzRendererResult, JsError> {
+		let main = archive.main_page();
+		Self::_new(
+			decode_html(&main),
+			main.url,
+			NetProvider::from_archive(archive.clone(), BlitzSchemeRegistry::default()),
+			shell,
+			canvas,
+			scale,
+			backend.unwrap_or_default(),
+			options.unwrap_or_default(),
+			None,
+		)
+		.await
+		.map(|x| JsValue::from(Array::of3(&x.0.into(), &x.1.into(), &x.2.into())).into())
+		.map_err(anyhow_to_obj)
+	}
+
+	fn _resize(
 		&mut self,
 		doc: &mut BlitzDocument,
 		canvas: OffscreenCanvas,
 		scale: f32,
 	) -> anyhow::Result<()> {
+		self.scene
+			.resize(canvas.clone(), scale)
+			.context("failed to resize canvas scene")?;
+
 		let mut viewport = doc.viewport();
 		viewport.window_size = (canvas.width(), canvas.height());
 		viewport.set_hidpi_scale(scale);
 
-		self.scene = CanvasVelloScene::new(canvas, scale)
-			.await
-			.context("failed to create vello scene")?;
+		self.stale = true;
 		Ok(())
 	}
+	/// Follows a resized canvas, a new canvas, or a new hidpi scale. The gpu device and renderer are
+	/// kept, so this is cheap enough to call on every resize event.
 	#[wasm_bindgen]
-	pub async fn resize(
+	pub fn resize(
 		&mut self,
 		doc: &mut BlitzDocument,
 		canvas: OffscreenCanvas,
 		scale: f32,
 	) -> Result<(), JsError> {
-		self._resize(doc, canvas, scale)
-			.await
-			.map_err(anyhow_to_obj)
+		self._resize(doc, canvas, scale).map_err(anyhow_to_obj)
 	}
 
-	fn loader(width: u32, scale: f32, time: f64, scene: &mut vello::Scene) -> u32 {
-		use vello::{
-			kurbo::{Affine, Rect},
-			peniko::{Color, Fill},
-		};
+	#[wasm_bindgen(getter)]
+	pub fn backend(&self) -> BlitzRenderBackend {
+		self.scene.backend()
+	}
+
+	#[wasm_bindgen(getter)]
+	pub fn loader_options(&self) -> BlitzLoaderOptions {
+		self.loader
+	}
+
+	#[wasm_bindgen(setter)]
+	pub fn set_loader_options(&mut self, options: &BlitzLoaderOptions) {
+		self.loader = *options;
+		self.stale = true;
+	}
+
+	/// The gpu context this renderer draws with, for sharing with other renderers.
+	#[wasm_bindgen(getter)]
+	pub fn gpu(&self) -> Option<BlitzGpuContext> {
+		self.scene.gpu().cloned()
+	}
+
+	#[wasm_bindgen(getter)]
+	pub fn options(&self) -> BlitzRendererOptions {
+		self.scene.options()
+	}
+
+	/// Applies new options from the next frame on, keeping the document and canvas.
+	#[wasm_bindgen(setter)]
+	pub fn set_options(&mut self, options: &BlitzRendererOptions) {
+		self.scene.set_options(*options);
+		self.stale = true;
+	}
+
+	fn loader(
+		options: &BlitzLoaderOptions,
+		fraction: Option<f64>,
+		width: u32,
+		bar_height: u32,
+		scale: f32,
+		time: f64,
+		scene: &mut impl PaintScene,
+	) {
+		use vello::{kurbo::Rect, peniko::Fill};
+
+		let bar_height = bar_height as f64;
+		let w = width as f64;
+		let track_color = color_from_rgba(options.track_color);
+		let bar_color = color_from_rgba(options.color);
+
+		if let Some(fraction) = fraction {
+			let track = Rect::new(0.0, 0.0, w, bar_height);
+			scene.fill(Fill::NonZero, Affine::IDENTITY, track_color, None, &track);
+			let bar = Rect::new(0.0, 0.0, w * fraction.clamp(0.0, 1.0), bar_height);
+			scene.fill(Fill::NonZero, Affine::IDENTITY, bar_color, None, &bar);
+			return;
+		}
 
-		let bar_height = 8.0 * scale as f64;
 		let cycle_time = 1.75;
 		let progress = (time % cycle_time) / cycle_time;
 
@@ -219,11 +589,6 @@ impl BlitzRenderer {
 		let track3_right = keyframe(progress, &[(0.0, 1.0), (0.5143, 1.0), (1.0, 0.0)]);
 
 		let margin = 4.0 * scale as f64;
-		let w = width as f64;
-
-		// Colors (approximating M3 secondary-container and primary)
-		let track_color = Color::from_rgba8(0, 0, 0, 0);
-		let bar_color = Color::from_rgb8(100, 150, 255);
 
 		// Draw tracks (background)
 		let track1_x = w * track1_left + margin;
@@ -261,35 +626,75 @@ impl BlitzRenderer {
 			let rect = Rect::new(bar2_x, 0.0, bar2_x + bar2_w, bar_height);
 			scene.fill(Fill::NonZero, Affine::IDENTITY, bar_color, None, &rect);
 		}
-
-		bar_height as u32
 	}
 
+	/// Paints a frame if the document changed since the last one, returning whether the canvas
+	/// was updated.
 	#[wasm_bindgen]
 	pub fn render(
 		&mut self,
 		doc: &mut BlitzDocument,
 		loading: bool,
 		time: f64,
-	) -> Result<(), JsError> {
+	) -> Result<bool, JsError> {
+		// the loader animates, and one more frame is needed to clear it once loading is done
+		let loader = loading || std::mem::replace(&mut self.was_loading, loading);
+		if !doc.take_needs_paint() && !loader && !self.stale {
+			return Ok(false);
+		}
+
 		self.scene
-			.render(|scene, width, height, scale| {
-				let offset = if loading {
-					Self::loader(width, scale, time, scene)
-				} else {
-					0
-				};
-
-				blitz_paint::paint_scene(
-					&mut VelloScenePainter::new(scene),
-					doc.doc(),
-					scale as f64,
-					width,
-					height - offset,
-					0,
-					offset,
-				);
+			.render(&BlitzRenderPaint {
+				doc,
+				loader: &self.loader,
+				loading,
+				time,
 			})
+			.map_err(anyhow_to_obj)?;
+		self.stale = false;
+		Ok(true)
+	}
+
+	#[wasm_bindgen]
+	pub async fn render_to_rgba(
+		&mut self,
+		doc: &BlitzDocument,
+		width: u32,
+		height: u32,
+		scale: f32,
+		loading: bool,
+		time: f64,
+	) -> Result<Vec<u8>, JsError> {
+		self.render_to_rgba_vec(doc, width, height, scale, loading, time)
+			.await
 			.map_err(anyhow_to_obj)
 	}
 }
+
+impl BlitzRenderer {
+	/// Paints `doc` into an RGBA8 buffer of `width * height * 4` bytes without touching the canvas.
+	pub async fn render_to_rgba_vec(
+		&mut self,
+		doc: &BlitzDocument,
+		width: u32,
+		height: u32,
+		scale: f32,
+		loading: bool,
+		time: f64,
+	) -> anyhow::Result<Vec<u8>> {
+		self.scene
+			.render_to_rgba(
+				width,
+				height,
+				scale,
+				&BlitzRenderPaint {
+					doc,
+					loader: &self.loader,
+					loading,
+					time,
+				},
+			)
+			.await
+			.context("failed to render to buffer")
+	}
+}
@@ -0,0 +1,591 @@
+use std::{collections::HashMap, fmt::Write};
+
+use anyrender::{NormalizedCoord, Paint, PaintRef, PaintScene};
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use vello::{
+	kurbo::{
+		self, Affine, BezPath, Cap, Join, PathEl, Point, Rect, RoundedRect, Shape, Stroke,
+		StrokeOpts,
+	},
+	peniko::{
+		BlendMode, Color, Fill, FontData, Gradient, GradientKind, ImageAlphaType, ImageFormat,
+		StyleRef, color::Srgb,
+	},
+};
+
+use crate::anyrender::glyph_run_path;
+
+const CATALOG_ID: usize = 1;
+const PAGES_ID: usize = 2;
+const RESOURCES_ID: usize = 3;
+
+const TOLERANCE: f64 = 0.1;
+
+/// How a shape is painted: filled with a fill rule or stroked.
+#[derive(Clone, Copy)]
+enum DrawStyle<'a> {
+	Fill(Fill),
+	Stroke(&'a Stroke),
+}
+
+/// Records scenes as pages of a PDF document.
+///
+/// Layers only support clipping and opacity; blend modes are ignored, group opacity is applied
+/// to each draw call separately and box shadow blurs are approximated.
+pub struct PdfScenePainter {
+	/// Object `n` is at `objects[n - 1]`, the first few are filled in by [`Self::finish`].
+	objects: Vec<Vec<u8>>,
+	pages: Vec<usize>,
+	ext_gstates: HashMap<u16, usize>,
+	shadings: Vec<usize>,
+	images: HashMap<u64, (usize, usize)>,
+	content: String,
+	alpha: Vec<f32>,
+	page: Option<(f64, f64, f64)>,
+}
+
+impl Default for PdfScenePainter {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl PdfScenePainter {
+	pub fn new() -> Self {
+		Self {
+			objects: vec![Vec::new(); RESOURCES_ID],
+			pages: Vec::new(),
+			ext_gstates: HashMap::new(),
+			shadings: Vec::new(),
+			images: HashMap::new(),
+			content: String::new(),
+			alpha: Vec::new(),
+			page: None,
+		}
+	}
+
+	/// Starts a page of `width` by `height` points. Scene coordinates are y-down with `scale`
+	/// scene units per point.
+	pub fn begin_page(&mut self, width: f64, height: f64, scale: f64) {
+		self.content.clear();
+		self.alpha.clear();
+		self.page = Some((width, height, scale));
+
+		let k = 1.0 / scale;
+		self.cm(Affine::new([k, 0.0, 0.0, -k, 0.0, height]));
+	}
+
+	pub fn end_page(&mut self) {
+		let Some((width, height, _)) = self.page.take() else {
+			return;
+		};
+		// close layers that were left open so the content stream stays balanced
+		for _ in 0..self.alpha.len() {
+			self.content.push_str("Q\n");
+		}
+		self.alpha.clear();
+
+		let contents = Self::stream("", self.content.as_bytes());
+		let contents = self.add_object(contents);
+		let page = self.add_object(
+			format!(
+				"<< /Type /Page /Parent {PAGES_ID} 0 R /MediaBox [0 0 {width:.3} {height:.3}] /Resources {RESOURCES_ID} 0 R /Contents {contents} 0 R >>"
+			)
+			.into_bytes(),
+		);
+		self.pages.push(page);
+	}
+
+	pub fn finish(mut self) -> Vec<u8> {
+		self.end_page();
+
+		let kids: Vec<String> = self.pages.iter().map(|x| format!("{x} 0 R")).collect();
+		self.objects[CATALOG_ID - 1] =
+			format!("<< /Type /Catalog /Pages {PAGES_ID} 0 R >>").into_bytes();
+		self.objects[PAGES_ID - 1] = format!(
+			"<< /Type /Pages /Kids [{}] /Count {} >>",
+			kids.join(" "),
+			self.pages.len()
+		)
+		.into_bytes();
+
+		let mut gstates = String::new();
+		for (key, id) in &self.ext_gstates {
+			let _ = write!(gstates, "/GA{key} {id} 0 R ");
+		}
+		let mut shadings = String::new();
+		for (i, id) in self.shadings.iter().enumerate() {
+			let _ = write!(shadings, "/Sh{i} {id} 0 R ");
+		}
+		let mut images = String::new();
+		for (i, id) in self.images.values() {
+			let _ = write!(images, "/Im{i} {id} 0 R ");
+		}
+		self.objects[RESOURCES_ID - 1] = format!(
+			"<< /ExtGState << {gstates}>> /Shading << {shadings}>> /XObject << {images}>> >>"
+		)
+		.into_bytes();
+
+		let mut out = b"%PDF-1.7\n%\xe2\xe3\xcf\xd3\n".to_vec();
+		let mut offsets = Vec::with_capacity(self.objects.len());
+		for (i, object) in self.objects.iter().enumerate() {
+			offsets.push(out.len());
+			out.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+			out.extend_from_slice(object);
+			out.extend_from_slice(b"\nendobj\n");
+		}
+
+		let xref = out.len();
+		let mut trailer = format!("xref\n0 {}\n0000000000 65535 f \n", self.objects.len() + 1);
+		for offset in offsets {
+			let _ = writeln!(trailer, "{offset:010} 00000 n ");
+		}
+		let _ = write!(
+			trailer,
+			"trailer\n<< /Size {} /Root {CATALOG_ID} 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+			self.objects.len() + 1
+		);
+		out.extend_from_slice(trailer.as_bytes());
+
+		out
+	}
+
+	fn add_object(&mut self, object: Vec<u8>) -> usize {
+		self.objects.push(object);
+		self.objects.len()
+	}
+
+	fn stream(dict: &str, data: &[u8]) -> Vec<u8> {
+		let data = compress_to_vec_zlib(data, 6);
+		let mut out = format!(
+			"<< {dict} /Filter /FlateDecode /Length {} >>\nstream\n",
+			data.len()
+		)
+		.into_bytes();
+		out.extend_from_slice(&data);
+		out.extend_from_slice(b"\nendstream");
+		out
+	}
+
+	fn cm(&mut self, transform: Affine) {
+		let [a, b, c, d, e, f] = transform.as_coeffs();
+		let _ = writeln!(self.content, "{a:.5} {b:.5} {c:.5} {d:.5} {e:.3} {f:.3} cm");
+	}
+
+	fn path(&mut self, path: impl IntoIterator<Item = PathEl>) {
+		let mut last = Point::ZERO;
+		for el in path {
+			let _ = match el {
+				PathEl::MoveTo(p) => {
+					last = p;
+					writeln!(self.content, "{:.3} {:.3} m", p.x, p.y)
+				}
+				PathEl::LineTo(p) => {
+					last = p;
+					writeln!(self.content, "{:.3} {:.3} l", p.x, p.y)
+				}
+				PathEl::QuadTo(p1, p2) => {
+					let c1 = last + (p1 - last) * (2.0 / 3.0);
+					let c2 = p2 + (p1 - p2) * (2.0 / 3.0);
+					last = p2;
+					writeln!(
+						self.content,
+						"{:.3} {:.3} {:.3} {:.3} {:.3} {:.3} c",
+						c1.x, c1.y, c2.x, c2.y, p2.x, p2.y
+					)
+				}
+				PathEl::CurveTo(p1, p2, p3) => {
+					last = p3;
+					writeln!(
+						self.content,
+						"{:.3} {:.3} {:.3} {:.3} {:.3} {:.3} c",
+						p1.x, p1.y, p2.x, p2.y, p3.x, p3.y
+					)
+				}
+				PathEl::ClosePath => writeln!(self.content, "h"),
+			};
+		}
+	}
+
+	fn set_alpha(&mut self, alpha: f32) {
+		let alpha = self.alpha.iter().product::<f32>() * alpha;
+		let key = (alpha.clamp(0.0, 1.0) * 1000.0).round() as u16;
+		if !self.ext_gstates.contains_key(&key) {
+			let alpha = key as f32 / 1000.0;
+			let id = self.add_object(
+				format!("<< /Type /ExtGState /ca {alpha:.3} /CA {alpha:.3} >>").into_bytes(),
+			);
+			self.ext_gstates.insert(key, id);
+		}
+		let _ = writeln!(self.content, "/GA{key} gs");
+	}
+
+	fn set_color(&mut self, color: Color, stroke: bool) {
+		let [r, g, b, a] = color.components;
+		self.set_alpha(a);
+		let op = if stroke { "RG" } else { "rg" };
+		let _ = writeln!(self.content, "{r:.4} {g:.4} {b:.4} {op}");
+	}
+
+	fn set_stroke(&mut self, style: &Stroke) {
+		let cap = match style.start_cap {
+			Cap::Butt => 0,
+			Cap::Round => 1,
+			Cap::Square => 2,
+		};
+		let join = match style.join {
+			Join::Miter => 0,
+			Join::Round => 1,
+			Join::Bevel => 2,
+		};
+		let dashes: Vec<String> = style
+			.dash_pattern
+			.iter()
+			.map(|x| format!("{x:.3}"))
+			.collect();
+		let _ = writeln!(
+			self.content,
+			"{:.3} w {cap} J {join} j {:.3} M [{}] {:.3} d",
+			style.width,
+			style.miter_limit,
+			dashes.join(" "),
+			style.dash_offset
+		);
+	}
+
+	fn gradient_function(gradient: &Gradient) -> Option<String> {
+		let stops: Vec<(f32, Color)> = gradient
+			.stops
+			.iter()
+			.map(|x| (x.offset, x.color.to_alpha_color::<Srgb>()))
+			.collect();
+		let rgb = |color: &Color| {
+			let [r, g, b, _] = color.components;
+			format!("[{r:.4} {g:.4} {b:.4}]")
+		};
+
+		let (first, last) = (stops.first()?, stops.last()?);
+		if stops.len() == 1 || first.0 >= last.0 {
+			return Some(format!(
+				"<< /FunctionType 2 /Domain [0 1] /C0 {} /C1 {} /N 1 >>",
+				rgb(&first.1),
+				rgb(&first.1)
+			));
+		}
+
+		let mut functions = String::new();
+		let mut bounds = String::new();
+		let mut encode = String::new();
+		for (i, pair) in stops.windows(2).enumerate() {
+			let _ = write!(
+				functions,
+				"<< /FunctionType 2 /Domain [0 1] /C0 {} /C1 {} /N 1 >> ",
+				rgb(&pair[0].1),
+				rgb(&pair[1].1)
+			);
+			if i > 0 {
+				let _ = write!(bounds, "{:.4} ", pair[0].0);
+			}
+			encode.push_str("0 1 ");
+		}
+
+		Some(format!(
+			"<< /FunctionType 3 /Domain [{:.4} {:.4}] /Functions [{functions}] /Bounds [{bounds}] /Encode [{encode}] >>",
+			first.0, last.0
+		))
+	}
+
+	/// Returns the resource name of a shading for `gradient`, or a solid fallback color.
+	fn shading(&mut self, gradient: &Gradient) -> Result<String, Color> {
+		let fallback = gradient
+			.stops
+			.first()
+			.map(|x| x.color.to_alpha_color::<Srgb>())
+			.unwrap_or(Color::TRANSPARENT);
+
+		let coords = match &gradient.kind {
+			GradientKind::Linear(pos) => format!(
+				"/ShadingType 2 /Coords [{:.3} {:.3} {:.3} {:.3}]",
+				pos.start.x, pos.start.y, pos.end.x, pos.end.y
+			),
+			GradientKind::Radial(pos) => format!(
+				"/ShadingType 3 /Coords [{:.3} {:.3} {:.3} {:.3} {:.3} {:.3}]",
+				pos.start_center.x,
+				pos.start_center.y,
+				pos.start_radius,
+				pos.end_center.x,
+				pos.end_center.y,
+				pos.end_radius
+			),
+			// pdf has no conic shading that matches
+			GradientKind::Sweep(_) => return Err(fallback),
+		};
+		let Some(function) = Self::gradient_function(gradient) else {
+			return Err(fallback);
+		};
+
+		let id = self.add_object(
+			format!(
+				"<< {coords} /ColorSpace /DeviceRGB /Function {function} /Extend [true true] >>"
+			)
+			.into_bytes(),
+		);
+		self.shadings.push(id);
+		Ok(format!("Sh{}", self.shadings.len() - 1))
+	}
+
+	/// Returns the resource name of an image XObject, or `None` if the image can't be embedded.
+	fn image(&mut self, image: &vello::peniko::ImageData) -> Option<String> {
+		if let Some((i, _)) = self.images.get(&image.data.id()) {
+			return Some(format!("Im{i}"));
+		}
+
+		let data = image.data.data();
+		if data.len() < image.width as usize * image.height as usize * 4 {
+			return None;
+		}
+		let mut rgb = Vec::with_capacity(data.len() / 4 * 3);
+		let mut alpha = Vec::with_capacity(data.len() / 4);
+		for px in data.chunks_exact(4) {
+			let [mut r, g, mut b, a] = [px[0], px[1], px[2], px[3]];
+			if image.format == ImageFormat::Bgra8 {
+				std::mem::swap(&mut r, &mut b);
+			}
+			let mut color = [r, g, b];
+			if image.alpha_type == ImageAlphaType::AlphaPremultiplied && a != 0 {
+				for c in &mut color {
+					*c = (*c as u16 * 255 / a as u16) as u8;
+				}
+			}
+			rgb.extend_from_slice(&color);
+			alpha.push(a);
+		}
+
+		let dict = format!(
+			"/Type /XObject /Subtype /Image /Width {} /Height {} /BitsPerComponent 8",
+			image.width, image.height
+		);
+		let smask = self.add_object(Self::stream(
+			&format!("{dict} /ColorSpace /DeviceGray"),
+			&alpha,
+		));
+		let id = self.add_object(Self::stream(
+			&format!("{dict} /ColorSpace /DeviceRGB /SMask {smask} 0 R"),
+			&rgb,
+		));
+
+		let i = self.images.len();
+		self.images.insert(image.data.id(), (i, id));
+		Some(format!("Im{i}"))
+	}
+
+	fn draw(
+		&mut self,
+		style: DrawStyle<'_>,
+		transform: Affine,
+		paint: PaintRef<'_>,
+		brush_transform: Option<Affine>,
+		shape: &impl Shape,
+	) {
+		self.content.push_str("q\n");
+		self.cm(transform);
+
+		match paint {
+			Paint::Solid(color) => {
+				match style {
+					DrawStyle::Fill(_) => self.set_color(color, false),
+					DrawStyle::Stroke(stroke) => {
+						self.set_color(color, true);
+						self.set_stroke(stroke);
+					}
+				}
+				self.path(shape.path_elements(TOLERANCE));
+				self.content.push_str(match style {
+					DrawStyle::Fill(Fill::NonZero) => "f\n",
+					DrawStyle::Fill(Fill::EvenOdd) => "f*\n",
+					DrawStyle::Stroke(_) => "S\n",
+				});
+			}
+			Paint::Gradient(gradient) => match self.shading(gradient) {
+				Ok(shading) => {
+					self.set_alpha(1.0);
+					self.clip_to(style, shape);
+					if let Some(brush_transform) = brush_transform {
+						self.cm(brush_transform);
+					}
+					let _ = writeln!(self.content, "/{shading} sh");
+				}
+				Err(fallback) => {
+					self.content.push_str("Q\n");
+					return self.draw(
+						style,
+						transform,
+						Paint::Solid(fallback),
+						brush_transform,
+						shape,
+					);
+				}
+			},
+			Paint::Image(image) => {
+				if let Some(name) = self.image(image.image) {
+					self.set_alpha(image.sampler.alpha);
+					self.clip_to(style, shape);
+					if let Some(brush_transform) = brush_transform {
+						self.cm(brush_transform);
+					}
+					let (w, h) = (image.image.width as f64, image.image.height as f64);
+					self.cm(Affine::new([w, 0.0, 0.0, -h, 0.0, h]));
+					let _ = writeln!(self.content, "/{name} Do");
+				}
+			}
+			_ => {}
+		}
+
+		self.content.push_str("Q\n");
+	}
+
+	fn clip_to(&mut self, style: DrawStyle<'_>, shape: &impl Shape) {
+		match style {
+			DrawStyle::Fill(fill) => {
+				self.path(shape.path_elements(TOLERANCE));
+				self.content.push_str(match fill {
+					Fill::NonZero => "W n\n",
+					Fill::EvenOdd => "W* n\n",
+				});
+			}
+			DrawStyle::Stroke(stroke) => {
+				let outline = kurbo::stroke(
+					shape.path_elements(TOLERANCE),
+					stroke,
+					&StrokeOpts::default(),
+					TOLERANCE,
+				);
+				self.path(outline);
+				self.content.push_str("W n\n");
+			}
+		}
+	}
+
+	fn push_clip(&mut self, transform: Affine, clip: &impl Shape, alpha: f32) {
+		self.content.push_str("q\n");
+		let mut path = BezPath::from_iter(clip.path_elements(TOLERANCE));
+		path.apply_affine(transform);
+		self.path(path);
+		self.content.push_str("W n\n");
+		self.alpha.push(alpha);
+	}
+}
+
+impl PaintScene for PdfScenePainter {
+	fn reset(&mut self) {
+		if let Some((width, height, scale)) = self.page {
+			self.begin_page(width, height, scale);
+		}
+	}
+
+	fn push_layer(
+		&mut self,
+		_blend: impl Into<BlendMode>,
+		alpha: f32,
+		transform: Affine,
+		clip: &impl Shape,
+	) {
+		self.push_clip(transform, clip, alpha);
+	}
+
+	fn push_clip_layer(&mut self, transform: Affine, clip: &impl Shape) {
+		self.push_clip(transform, clip, 1.0);
+	}
+
+	fn pop_layer(&mut self) {
+		if self.alpha.pop().is_some() {
+			self.content.push_str("Q\n");
+		}
+	}
+
+	fn stroke<'a>(
+		&mut self,
+		style: &Stroke,
+		transform: Affine,
+		paint_ref: impl Into<PaintRef<'a>>,
+		brush_transform: Option<Affine>,
+		shape: &impl Shape,
+	) {
+		self.draw(
+			DrawStyle::Stroke(style),
+			transform,
+			paint_ref.into(),
+			brush_transform,
+			shape,
+		);
+	}
+
+	fn fill<'a>(
+		&mut self,
+		style: Fill,
+		transform: Affine,
+		paint: impl Into<PaintRef<'a>>,
+		brush_transform: Option<Affine>,
+		shape: &impl Shape,
+	) {
+		self.draw(
+			DrawStyle::Fill(style),
+			transform,
+			paint.into(),
+			brush_transform,
+			shape,
+		);
+	}
+
+	fn draw_glyphs<'a, 's: 'a>(
+		&'a mut self,
+		font: &'a FontData,
+		font_size: f32,
+		_hint: bool,
+		normalized_coords: &'a [NormalizedCoord],
+		style: impl Into<StyleRef<'a>>,
+		paint: impl Into<PaintRef<'a>>,
+		brush_alpha: f32,
+		transform: Affine,
+		glyph_transform: Option<Affine>,
+		glyphs: impl Iterator<Item = anyrender::Glyph>,
+	) {
+		let path = glyph_run_path(font, font_size, normalized_coords, glyph_transform, glyphs);
+		if path.is_empty() {
+			return;
+		}
+
+		let style = match style.into() {
+			StyleRef::Fill(fill) => DrawStyle::Fill(fill),
+			StyleRef::Stroke(stroke) => DrawStyle::Stroke(stroke),
+		};
+		self.alpha.push(brush_alpha);
+		self.draw(style, transform, paint.into(), None, &path);
+		self.alpha.pop();
+	}
+
+	fn draw_box_shadow(
+		&mut self,
+		transform: Affine,
+		rect: Rect,
+		brush: Color,
+		radius: f64,
+		std_dev: f64,
+	) {
+		// approximate the blur with a few stacked translucent rounded rects
+		const STEPS: usize = 4;
+		let brush = brush.multiply_alpha(1.0 / STEPS as f32);
+		for i in 0..STEPS {
+			let spread = std_dev * (1.5 - i as f64);
+			let shape =
+				RoundedRect::from_rect(rect.inflate(spread, spread), (radius + spread).max(0.0));
+			self.draw(
+				DrawStyle::Fill(Fill::NonZero),
+				transform,
+				Paint::Solid(brush),
+				None,
+				&shape,
+			);
+		}
+	}
+}
@@ -0,0 +1,249 @@
+use anyhow::{Result, bail};
+use anyrender::PaintScene;
+use blitz_dom::BaseDocument;
+use vello::kurbo::{Affine, Rect};
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::{anyrender::TransformedScenePainter, now, pdf::PdfScenePainter};
+
+const PX_PER_IN: f32 = 96.0;
+const PX_PER_MM: f32 = PX_PER_IN / 25.4;
+const PT_PER_PX: f64 = 0.75;
+
+/// Page setup for [`print_to_pdf`]. All lengths are in CSS pixels.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy)]
+pub struct BlitzPrintOptions {
+	pub page_width: f32,
+	pub page_height: f32,
+	pub margin_top: f32,
+	pub margin_right: f32,
+	pub margin_bottom: f32,
+	pub margin_left: f32,
+	pub landscape: bool,
+	/// Let `@page` rules in the document override the page size and margins above.
+	pub prefer_css_page_size: bool,
+}
+
+impl Default for BlitzPrintOptions {
+	fn default() -> Self {
+		let margin = 10.0 * PX_PER_MM;
+		Self {
+			page_width: 210.0 * PX_PER_MM,
+			page_height: 297.0 * PX_PER_MM,
+			margin_top: margin,
+			margin_right: margin,
+			margin_bottom: margin,
+			margin_left: margin,
+			landscape: false,
+			prefer_css_page_size: true,
+		}
+	}
+}
+
+#[wasm_bindgen]
+impl BlitzPrintOptions {
+	/// A4 portrait with 1cm margins.
+	#[wasm_bindgen(constructor)]
+	pub fn new() -> Self {
+		Self::default()
+	}
+}
+
+fn parse_length(value: &str) -> Option<f32> {
+	let value = value.trim();
+	let split = value
+		.find(|c: char| c.is_ascii_alphabetic())
+		.unwrap_or(value.len());
+	let (num, unit) = value.split_at(split);
+	let num: f32 = num.trim().parse().ok()?;
+
+	Some(
+		num * match unit.to_ascii_lowercase().as_str() {
+			"" if num == 0.0 => 0.0,
+			"px" => 1.0,
+			"in" => PX_PER_IN,
+			"cm" => PX_PER_MM * 10.0,
+			"mm" => PX_PER_MM,
+			"q" => PX_PER_MM / 4.0,
+			"pt" => PX_PER_IN / 72.0,
+			"pc" => PX_PER_IN / 6.0,
+			_ => return None,
+		},
+	)
+}
+
+fn named_page_size(name: &str) -> Option<(f32, f32)> {
+	let (width, height) = match name.to_ascii_lowercase().as_str() {
+		"a5" => (148.0 * PX_PER_MM, 210.0 * PX_PER_MM),
+		"a4" => (210.0 * PX_PER_MM, 297.0 * PX_PER_MM),
+		"a3" => (297.0 * PX_PER_MM, 420.0 * PX_PER_MM),
+		"b5" => (176.0 * PX_PER_MM, 250.0 * PX_PER_MM),
+		"b4" => (250.0 * PX_PER_MM, 353.0 * PX_PER_MM),
+		"jis-b5" => (182.0 * PX_PER_MM, 257.0 * PX_PER_MM),
+		"jis-b4" => (257.0 * PX_PER_MM, 364.0 * PX_PER_MM),
+		"letter" => (8.5 * PX_PER_IN, 11.0 * PX_PER_IN),
+		"legal" => (8.5 * PX_PER_IN, 14.0 * PX_PER_IN),
+		"ledger" => (11.0 * PX_PER_IN, 17.0 * PX_PER_IN),
+		_ => return None,
+	};
+	Some((width, height))
+}
+
+impl BlitzPrintOptions {
+	fn apply_page_size(&mut self, value: &str) {
+		let mut lengths = Vec::new();
+		for part in value.split_whitespace() {
+			match part.to_ascii_lowercase().as_str() {
+				"auto" => {}
+				"landscape" => self.landscape = true,
+				"portrait" => self.landscape = false,
+				_ => {
+					if let Some((width, height)) = named_page_size(part) {
+						(self.page_width, self.page_height) = (width, height);
+					} else if let Some(length) = parse_length(part) {
+						lengths.push(length);
+					}
+				}
+			}
+		}
+
+		match lengths[..] {
+			[size] => (self.page_width, self.page_height) = (size, size),
+			[width, height] => {
+				// explicit lengths already have an orientation
+				(self.page_width, self.page_height) = (width, height);
+				self.landscape = false;
+			}
+			_ => {}
+		}
+	}
+
+	fn apply_margin(&mut self, value: &str) {
+		let lengths: Option<Vec<f32>> = value.split_whitespace().map(parse_length).collect();
+		let Some(lengths) = lengths else {
+			return;
+		};
+
+		let (top, right, bottom, left) = match lengths[..] {
+			[all] => (all, all, all, all),
+			[vertical, horizontal] => (vertical, horizontal, vertical, horizontal),
+			[top, horizontal, bottom] => (top, horizontal, bottom, horizontal),
+			[top, right, bottom, left] => (top, right, bottom, left),
+			_ => return,
+		};
+		self.margin_top = top;
+		self.margin_right = right;
+		self.margin_bottom = bottom;
+		self.margin_left = left;
+	}
+
+	/// Applies the declarations of an `@page` rule, as serialized by stylo.
+	fn apply_page_rule(&mut self, declarations: &str) {
+		for declaration in declarations.split(';') {
+			let Some((name, value)) = declaration.split_once(':') else {
+				continue;
+			};
+			let value = value.trim().trim_end_matches("!important").trim();
+			match name.trim().to_ascii_lowercase().as_str() {
+				"size" => self.apply_page_size(value),
+				"margin" => self.apply_margin(value),
+				"margin-top" => self.margin_top = parse_length(value).unwrap_or(self.margin_top),
+				"margin-right" => {
+					self.margin_right = parse_length(value).unwrap_or(self.margin_right)
+				}
+				"margin-bottom" => {
+					self.margin_bottom = parse_length(value).unwrap_or(self.margin_bottom)
+				}
+				"margin-left" => self.margin_left = parse_length(value).unwrap_or(self.margin_left),
+				_ => {}
+			}
+		}
+	}
+
+	/// Resolves `@page` rules and orientation into the final page box. Page selectors and
+	/// margin boxes are ignored.
+	fn resolve(mut self, doc: &BaseDocument) -> Self {
+		if self.prefer_css_page_size {
+			for rule in doc.page_rules() {
+				self.apply_page_rule(&rule);
+			}
+		}
+
+		if self.landscape && self.page_width < self.page_height {
+			(self.page_width, self.page_height) = (self.page_height, self.page_width);
+		}
+		self
+	}
+}
+
+/// Lays the document out at the page content width with the `print` media type, splits it
+/// into pages and paints each one into a PDF.
+pub fn print_to_pdf(doc: &mut BaseDocument, options: BlitzPrintOptions) -> Result<Vec<u8>> {
+	// `@page` rules can be inside `@media print`
+	doc.set_media_type("print");
+	let page = options.resolve(doc);
+	let content_width = page.page_width - page.margin_left - page.margin_right;
+	let content_height = page.page_height - page.margin_top - page.margin_bottom;
+	if content_width <= 0.0 || content_height <= 0.0 {
+		doc.set_media_type("screen");
+		bail!("page margins leave no room for content");
+	}
+
+	let original_viewport = doc.viewport().clone();
+	let original_scroll = doc.viewport_scroll();
+	let scale = original_viewport.scale();
+	let (width, height) = (
+		(content_width * scale).round() as u32,
+		(content_height * scale).round() as u32,
+	);
+
+	doc.viewport_mut().window_size = (width, height);
+	// a new viewport gets a new device, which is for the screen again
+	doc.set_media_type("print");
+	doc.resolve(now());
+
+	let layout = doc.root_element().final_layout;
+	let total_height = layout.size.height.max(layout.content_size.height);
+	let pages = (total_height / content_height).ceil().max(1.0) as usize;
+
+	let scale = scale as f64;
+	let margin = Affine::translate((
+		page.margin_left as f64 * scale,
+		page.margin_top as f64 * scale,
+	));
+	let clip = Rect::new(0.0, 0.0, width as f64, height as f64);
+
+	let mut pdf = PdfScenePainter::new();
+	for i in 0..pages {
+		let mut scroll = original_scroll;
+		scroll.x = 0.0;
+		scroll.y = i as f64 * content_height as f64;
+		doc.set_viewport_scroll(scroll);
+
+		pdf.begin_page(
+			page.page_width as f64 * PT_PER_PX,
+			page.page_height as f64 * PT_PER_PX,
+			scale / PT_PER_PX,
+		);
+		pdf.push_clip_layer(margin, &clip);
+		blitz_paint::paint_scene(
+			&mut TransformedScenePainter::new(&mut pdf, margin),
+			doc,
+			scale,
+			width,
+			height,
+			0,
+			0,
+		);
+		pdf.pop_layer();
+		pdf.end_page();
+	}
+
+	doc.set_viewport_scroll(original_scroll);
+	*doc.viewport_mut() = original_viewport;
+	doc.set_media_type("screen");
+	doc.resolve(now());
+
+	Ok(pdf.finish())
+}
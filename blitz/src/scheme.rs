@@ -0,0 +1,129 @@
+use std::{
+	collections::HashMap,
+	sync::{Arc, Mutex},
+};
+
+use blitz_traits::net::Bytes;
+use js_sys::Function;
+use wasm_bindgen::{JsCast, JsError, prelude::wasm_bindgen};
+
+use crate::{
+	anyhow_to_obj,
+	blitz_net::{BlitzFetcherFunction, FetchedResponse},
+	bundle::BlitzBundle,
+};
+
+/// Schemes the provider handles itself, which can't be taken over.
+const RESERVED: &[&str] = &["http", "https", "data"];
+
+/// Answers requests for a custom scheme instead of the fetcher.
+#[derive(Clone)]
+pub enum SchemeHandler {
+	/// Called like the fetcher, with a `Request`, and may return anything the fetcher can.
+	Js(Function),
+	Bundle(BlitzBundle),
+	/// Returns the content type and body for a URL, or `None` for a 404.
+	Native(Arc<dyn Fn(&str) -> Option<(String, Bytes)> + Send + Sync>),
+}
+
+/// Answers a request with a native handler, with a 404 if it has nothing for the URL.
+pub(crate) fn serve_native(
+	func: &dyn Fn(&str) -> Option<(String, Bytes)>,
+	url: &str,
+) -> FetchedResponse {
+	let (status, status_text, headers, body) = match func(url) {
+		Some((mime, body)) => (200, "OK", vec![("content-type".to_string(), mime)], body),
+		None => (404, "Not Found", Vec::new(), Bytes::new()),
+	};
+	FetchedResponse {
+		url: url.to_string(),
+		status,
+		status_text: status_text.to_string(),
+		headers,
+		body,
+		from_cache: false,
+	}
+}
+
+/// Handlers for custom schemes like `app://`, whose requests never reach the fetcher, the
+/// HTTP cache or the per-origin queue.
+///
+/// blitz-dom starts fetching while it parses, so a registry has to be passed to
+/// [`BlitzRenderer::new`](crate::BlitzRenderer::new) for the document's own resources to use
+/// it. Handles are shared, so schemes registered later through
+/// [`BlitzDocument::schemes`](crate::document::BlitzDocument::schemes) apply to requests made
+/// after that.
+#[wasm_bindgen]
+#[derive(Clone, Default)]
+pub struct BlitzSchemeRegistry {
+	handlers: Arc<Mutex<HashMap<String, SchemeHandler>>>,
+}
+// SAFETY: the JS handlers are only ever touched from the one thread wasm runs on. Native
+// handlers are Send + Sync themselves.
+unsafe impl Send for BlitzSchemeRegistry {}
+unsafe impl Sync for BlitzSchemeRegistry {}
+
+/// Schemes are matched without their colon and case insensitively, like URLs parse them.
+fn normalize_scheme(scheme: &str) -> String {
+	scheme.trim_end_matches(':').to_ascii_lowercase()
+}
+
+impl BlitzSchemeRegistry {
+	pub fn register(&self, scheme: &str, handler: SchemeHandler) -> anyhow::Result<()> {
+		let scheme = normalize_scheme(scheme);
+		if RESERVED.contains(&scheme.as_str()) {
+			anyhow::bail!("the {scheme} scheme can't be overridden");
+		}
+		self.handlers.lock().unwrap().insert(scheme, handler);
+		Ok(())
+	}
+
+	pub(crate) fn get(&self, scheme: &str) -> Option<SchemeHandler> {
+		self.handlers.lock().unwrap().get(scheme).cloned()
+	}
+}
+
+#[wasm_bindgen]
+impl BlitzSchemeRegistry {
+	#[wasm_bindgen(constructor)]
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Handles `scheme` with a function called like the fetcher.
+	pub fn register_function(
+		&self,
+		scheme: &str,
+		handler: BlitzFetcherFunction,
+	) -> Result<(), JsError> {
+		self.register(scheme, SchemeHandler::Js(handler.unchecked_into()))
+			.map_err(anyhow_to_obj)
+	}
+
+	/// Serves `scheme` from the files of a bundle.
+	pub fn register_bundle(&self, scheme: &str, bundle: &BlitzBundle) -> Result<(), JsError> {
+		self.register(scheme, SchemeHandler::Bundle(bundle.clone()))
+			.map_err(anyhow_to_obj)
+	}
+
+	/// Removes the handler for `scheme`, returning whether there was one.
+	pub fn unregister(&self, scheme: &str) -> bool {
+		self.handlers
+			.lock()
+			.unwrap()
+			.remove(&normalize_scheme(scheme))
+			.is_some()
+	}
+
+	pub fn schemes(&self) -> Vec<String> {
+		let mut schemes = self
+			.handlers
+			.lock()
+			.unwrap()
+			.keys()
+			.cloned()
+			.collect::<Vec<_>>();
+		schemes.sort();
+		schemes
+	}
+}
@@ -0,0 +1,150 @@
+use std::io::Cursor;
+
+use anyhow::{Context, Result, bail};
+use anyrender::PaintScene;
+use blitz_dom::BaseDocument;
+use image::{ImageFormat, RgbaImage};
+use vello::{kurbo::Affine, peniko::color::palette};
+
+use crate::{
+	anyrender::{SvgScenePainter, TransformedScenePainter},
+	canvas::{CanvasPaint, rasterize_cpu},
+};
+
+// vello_cpu stores dimensions as u16
+const MAX_DIMENSION: f32 = u16::MAX as f32;
+
+struct ScreenshotPaint<'a> {
+	doc: &'a BaseDocument,
+	x: f64,
+	y: f64,
+}
+impl CanvasPaint for ScreenshotPaint<'_> {
+	fn paint(&self, scene: &mut impl PaintScene, width: u32, height: u32, scale: f32) {
+		let scale = scale as f64;
+		blitz_paint::paint_scene(
+			&mut TransformedScenePainter::new(
+				scene,
+				Affine::translate((-self.x * scale, -self.y * scale)),
+			),
+			self.doc,
+			scale,
+			width,
+			height,
+			0,
+			0,
+		);
+	}
+}
+
+/// A rectangle of the page in CSS pixels.
+#[derive(Debug, Clone, Copy)]
+pub struct ScreenshotRect {
+	pub x: f32,
+	pub y: f32,
+	pub width: f32,
+	pub height: f32,
+}
+
+impl ScreenshotRect {
+	/// The whole scrollable page, or at least the viewport if the page is smaller.
+	pub fn page(doc: &BaseDocument) -> Self {
+		let viewport = doc.viewport();
+		let scale = viewport.scale();
+		let layout = doc.root_element().final_layout;
+
+		Self {
+			x: 0.0,
+			y: 0.0,
+			width: (viewport.window_size.0 as f32 / scale)
+				.max(layout.size.width)
+				.max(layout.content_size.width),
+			height: (viewport.window_size.1 as f32 / scale)
+				.max(layout.size.height)
+				.max(layout.content_size.height),
+		}
+	}
+
+	/// The border box of a node.
+	pub fn node(doc: &BaseDocument, node: usize) -> Result<Self> {
+		let node = doc.get_node(node).context("invalid node")?;
+		let position = node.absolute_position(0.0, 0.0);
+		let size = node.final_layout.size;
+
+		Ok(Self {
+			x: position.x,
+			y: position.y,
+			width: size.width,
+			height: size.height,
+		})
+	}
+
+	fn device_size(&self, scale: f32) -> Result<(u32, u32)> {
+		let width = (self.width * scale).ceil().min(MAX_DIMENSION) as u32;
+		let height = (self.height * scale).ceil().min(MAX_DIMENSION) as u32;
+		if width == 0 || height == 0 {
+			bail!("cannot screenshot an empty {width}x{height} area");
+		}
+		Ok((width, height))
+	}
+}
+
+/// Paints in page coordinates rather than relative to the viewport.
+fn paint_unscrolled<T>(doc: &mut BaseDocument, func: impl FnOnce(&BaseDocument) -> T) -> T {
+	let scroll = doc.viewport_scroll();
+	let mut unscrolled = scroll;
+	unscrolled.x = 0.0;
+	unscrolled.y = 0.0;
+	doc.set_viewport_scroll(unscrolled);
+
+	let ret = func(doc);
+
+	doc.set_viewport_scroll(scroll);
+	ret
+}
+
+/// Rasterizes part of the page on the CPU and encodes it as a PNG.
+pub fn screenshot_png(doc: &mut BaseDocument, rect: ScreenshotRect) -> Result<Vec<u8>> {
+	let scale = doc.viewport().scale();
+	let (width, height) = rect.device_size(scale)?;
+
+	let rgba = paint_unscrolled(doc, |doc| {
+		rasterize_cpu(
+			width,
+			height,
+			scale,
+			palette::css::WHITE,
+			&ScreenshotPaint {
+				doc,
+				x: rect.x as f64,
+				y: rect.y as f64,
+			},
+		)
+	});
+
+	let image = RgbaImage::from_raw(width, height, rgba).context("invalid image buffer")?;
+	let mut png = Cursor::new(Vec::new());
+	image
+		.write_to(&mut png, ImageFormat::Png)
+		.context("failed to encode png")?;
+
+	Ok(png.into_inner())
+}
+
+/// Records part of the page as SVG markup sized in CSS pixels.
+pub fn screenshot_svg(doc: &mut BaseDocument, rect: ScreenshotRect) -> Result<String> {
+	let scale = doc.viewport().scale();
+	let (width, height) = rect.device_size(scale)?;
+
+	let mut painter = SvgScenePainter::new();
+	paint_unscrolled(doc, |doc| {
+		ScreenshotPaint {
+			doc,
+			x: rect.x as f64,
+			y: rect.y as f64,
+		}
+		.paint(&mut painter, width, height, scale)
+	});
+
+	Ok(painter.finish(width, height, rect.width, rect.height))
+}
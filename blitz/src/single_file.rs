@@ -0,0 +1,375 @@
+use base64::{Engine, prelude::BASE64_STANDARD};
+use blitz_dom::{BaseDocument, Node};
+use blitz_traits::net::Bytes;
+use serde_json::{Value, json};
+use web_sys::Url;
+
+/// Looks up the content type and body fetched for a URL.
+type Resources<'a> = &'a dyn Fn(&str) -> Option<(String, Bytes)>;
+
+/// How deep `@import`s are followed, which also stops import cycles.
+const MAX_IMPORT_DEPTH: u32 = 8;
+
+/// Applies the recorded form values and scroll positions when the file is opened in a browser.
+const RESTORE_SCRIPT: &str = r#"<script>
+addEventListener("load", () => {
+	const state = JSON.parse(document.getElementById("blitz-saved-state").textContent);
+	const find = (path) => path.reduce((el, i) => el && el.children[i], document.documentElement);
+	for (const control of state.controls) {
+		const el = find(control.path);
+		if (!el) continue;
+		if ("checked" in control) el.checked = control.checked;
+		else el.value = control.value;
+	}
+	for (const scrolled of state.scrolled) find(scrolled.path)?.scrollTo(scrolled.x, scrolled.y);
+	scrollTo(state.scroll.x, state.scroll.y);
+});
+</script>"#;
+
+fn find_ignore_case(haystack: &str, needle: &str) -> Option<usize> {
+	haystack
+		.as_bytes()
+		.windows(needle.len())
+		.position(|x| x.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+fn unescape_attr(value: &str) -> String {
+	let mut out = String::with_capacity(value.len());
+	let mut rest = value;
+	while let Some(start) = rest.find('&') {
+		out.push_str(&rest[..start]);
+		rest = &rest[start..];
+		let Some(end) = rest.find(';').filter(|x| *x < 10) else {
+			out.push('&');
+			rest = &rest[1..];
+			continue;
+		};
+		let entity = &rest[1..end];
+		let decoded = match entity {
+			"amp" => Some('&'),
+			"quot" => Some('"'),
+			"apos" => Some('\''),
+			"lt" => Some('<'),
+			"gt" => Some('>'),
+			"nbsp" => Some('\u{a0}'),
+			_ => entity
+				.strip_prefix("#x")
+				.or_else(|| entity.strip_prefix("#X"))
+				.map(|x| u32::from_str_radix(x, 16))
+				.or_else(|| entity.strip_prefix('#').map(str::parse))
+				.and_then(Result::ok)
+				.and_then(char::from_u32),
+		};
+		match decoded {
+			Some(decoded) => {
+				out.push(decoded);
+				rest = &rest[end + 1..];
+			}
+			None => {
+				out.push('&');
+				rest = &rest[1..];
+			}
+		}
+	}
+	out.push_str(rest);
+	out
+}
+
+fn escape_attr(value: &str) -> String {
+	value.replace('&', "&amp;").replace('"', "&quot;")
+}
+
+/// Turns a reference into a data URL if its body was fetched, or an absolute URL otherwise.
+fn inline_url(raw: &str, base: &str, resources: Resources, depth: u32) -> String {
+	let raw = raw.trim();
+	if raw.is_empty() || raw.starts_with('#') || raw.starts_with("data:") {
+		return raw.to_string();
+	}
+	let Ok(url) = Url::new_with_base(raw, base) else {
+		return raw.to_string();
+	};
+	if url.protocol() == "javascript:" {
+		return raw.to_string();
+	}
+	let fragment = url.hash();
+	url.set_hash("");
+
+	let url = url.href();
+	let Some((mime, body)) = resources(&url) else {
+		return format!("{url}{fragment}");
+	};
+	// stylesheets are moved out from under their URL, so their own references have to go too
+	let body = if mime == "text/css" && depth < MAX_IMPORT_DEPTH {
+		Bytes::from(inline_css(
+			&String::from_utf8_lossy(&body),
+			&url,
+			resources,
+			depth + 1,
+		))
+	} else {
+		body
+	};
+	format!(
+		"data:{mime};base64,{}{fragment}",
+		BASE64_STANDARD.encode(&body)
+	)
+}
+
+/// Inlines the `url()`s and `@import`s of a stylesheet.
+fn inline_css(css: &str, base: &str, resources: Resources, depth: u32) -> String {
+	let mut out = String::with_capacity(css.len());
+	let mut rest = css;
+	loop {
+		let next = [
+			find_ignore_case(rest, "url("),
+			find_ignore_case(rest, "@import"),
+		]
+		.into_iter()
+		.flatten()
+		.min();
+		let Some(start) = next else {
+			break;
+		};
+		out.push_str(&rest[..start]);
+		rest = &rest[start..];
+
+		if rest.as_bytes()[0] == b'@' {
+			out.push_str(&rest[..7]);
+			rest = &rest[7..];
+			// only the string form, `url()` is handled like any other
+			let trimmed = rest.trim_start();
+			let Some(quote) = trimmed.chars().next().filter(|x| *x == '"' || *x == '\'') else {
+				continue;
+			};
+			let Some(end) = trimmed[1..].find(quote) else {
+				continue;
+			};
+			let url = inline_url(&trimmed[1..end + 1], base, resources, depth);
+			out.push_str(&format!(" url(\"{url}\")"));
+			rest = &trimmed[end + 2..];
+			continue;
+		}
+
+		let inner = &rest[4..];
+		let Some(end) = inner.find(')') else {
+			break;
+		};
+		let value = inner[..end].trim().trim_matches(|x| x == '"' || x == '\'');
+		let url = inline_url(value, base, resources, depth);
+		out.push_str(&format!("url(\"{url}\")"));
+		rest = &inner[end + 1..];
+	}
+	out.push_str(rest);
+	out
+}
+
+/// Inlines a `srcset`, made of URLs each followed by an optional descriptor.
+fn inline_srcset(srcset: &str, base: &str, resources: Resources) -> String {
+	srcset
+		.split(',')
+		.map(|candidate| {
+			let candidate = candidate.trim();
+			let (url, descriptor) = candidate
+				.split_once(char::is_whitespace)
+				.unwrap_or((candidate, ""));
+			let url = inline_url(url, base, resources, 0);
+			if descriptor.is_empty() {
+				url
+			} else {
+				format!("{url} {}", descriptor.trim())
+			}
+		})
+		.collect::<Vec<_>>()
+		.join(", ")
+}
+
+/// Rewrites the attributes of the tag at the start of `html`, returning it, its lowercased
+/// name and what comes after it.
+fn inline_tag<'a>(html: &'a str, base: &str, resources: Resources) -> (String, String, &'a str) {
+	// closing tags keep their slash in the name
+	let start = if html[1..].starts_with('/') { 2 } else { 1 };
+	let name_end = html[start..]
+		.find(|x: char| x.is_whitespace() || x == '>' || x == '/')
+		.map_or(html.len(), |x| x + start);
+	let name = html[1..name_end].to_ascii_lowercase();
+	let mut out = html[..name_end].to_string();
+	let mut rest = &html[name_end..];
+
+	loop {
+		let trimmed = rest.trim_start();
+		let Some(next) = trimmed.chars().next() else {
+			return (out, name, "");
+		};
+		if next == '>' {
+			out.push('>');
+			return (out, name, &trimmed[1..]);
+		}
+		if next == '/' {
+			out.push_str(&rest[..rest.len() - trimmed.len() + 1]);
+			rest = &trimmed[1..];
+			continue;
+		}
+
+		let attr_end = trimmed
+			.find(|x: char| x.is_whitespace() || x == '=' || x == '>' || x == '/')
+			.unwrap_or(trimmed.len());
+		if attr_end == 0 {
+			// a stray `=`, which isn't worth fixing
+			out.push_str(&trimmed[..1]);
+			rest = &trimmed[1..];
+			continue;
+		}
+		let attr = &trimmed[..attr_end];
+		let after = trimmed[attr_end..].trim_start();
+		let Some(after) = after.strip_prefix('=') else {
+			out.push(' ');
+			out.push_str(attr);
+			rest = &trimmed[attr_end..];
+			continue;
+		};
+
+		let after = after.trim_start();
+		let (value, after) = match after.chars().next() {
+			Some(quote @ ('"' | '\'')) => {
+				let end = after[1..].find(quote).map_or(after.len(), |x| x + 1);
+				(&after[1..end], after.get(end + 1..).unwrap_or_default())
+			}
+			_ => {
+				let end = after
+					.find(|x: char| x.is_whitespace() || x == '>')
+					.unwrap_or(after.len());
+				(&after[..end], &after[end..])
+			}
+		};
+		let value = unescape_attr(value);
+		let value = match attr.to_ascii_lowercase().as_str() {
+			"src" | "href" | "poster" => inline_url(&value, base, resources, 0),
+			"srcset" => inline_srcset(&value, base, resources),
+			"style" => inline_css(&value, base, resources, 0),
+			_ => value,
+		};
+		out.push_str(&format!(" {attr}=\"{}\"", escape_attr(&value)));
+		rest = after;
+	}
+}
+
+/// Inlines the resources referenced by serialized markup.
+fn inline_html(html: &str, base: &str, resources: Resources) -> String {
+	let mut out = String::with_capacity(html.len());
+	let mut rest = html;
+	while let Some(start) = rest.find('<') {
+		out.push_str(&rest[..start]);
+		rest = &rest[start..];
+
+		if rest.starts_with("<!--") {
+			let end = rest.find("-->").map_or(rest.len(), |x| x + 3);
+			out.push_str(&rest[..end]);
+			rest = &rest[end..];
+			continue;
+		}
+
+		let (tag, name, after) = inline_tag(rest, base, resources);
+		out.push_str(&tag);
+		rest = after;
+
+		// their contents aren't markup
+		if name == "style" || name == "script" {
+			let end = find_ignore_case(rest, &format!("</{name}")).unwrap_or(rest.len());
+			if name == "style" {
+				out.push_str(&inline_css(&rest[..end], base, resources, 0));
+			} else {
+				out.push_str(&rest[..end]);
+			}
+			rest = &rest[end..];
+		}
+	}
+	out.push_str(rest);
+	out
+}
+
+/// Indices of the element children leading from the root element to `node`, which is how the
+/// restore script finds it again.
+fn element_path(doc: &BaseDocument, node: &Node) -> Option<Vec<usize>> {
+	let root = doc.root_element().id;
+	let mut path = Vec::new();
+	let mut node = node;
+	while node.id != root {
+		let parent = doc.get_node(node.parent?)?;
+		let index = parent
+			.children
+			.iter()
+			.filter_map(|x| doc.get_node(*x))
+			.filter(|x| x.is_element())
+			.position(|x| x.id == node.id)?;
+		path.push(index);
+		node = parent;
+	}
+	path.reverse();
+	Some(path)
+}
+
+/// Form values and scroll offsets, which live in the document rather than its markup.
+fn saved_state(doc: &BaseDocument) -> Value {
+	let mut controls = Vec::new();
+	let mut scrolled = Vec::new();
+
+	let mut stack = vec![doc.root_element().id];
+	while let Some(id) = stack.pop() {
+		let Some(node) = doc.get_node(id) else {
+			continue;
+		};
+		stack.extend(node.children.iter().rev());
+		let Some(element) = node.element_data() else {
+			continue;
+		};
+
+		let control = if let Some(input) = element.text_input_data() {
+			Some(json!({ "value": input.editor.text().to_string() }))
+		} else {
+			element
+				.checkbox_input_checked()
+				.map(|checked| json!({ "checked": checked }))
+		};
+		if let Some(mut control) = control
+			&& let Some(path) = element_path(doc, node)
+		{
+			control["path"] = json!(path);
+			controls.push(control);
+		}
+
+		let offset = node.scroll_offset;
+		if (offset.x != 0.0 || offset.y != 0.0)
+			&& let Some(path) = element_path(doc, node)
+		{
+			scrolled.push(json!({ "path": path, "x": offset.x, "y": offset.y }));
+		}
+	}
+
+	let scroll = doc.viewport_scroll();
+	json!({
+		"scroll": { "x": scroll.x, "y": scroll.y },
+		"controls": controls,
+		"scrolled": scrolled,
+	})
+}
+
+/// Serializes `doc` into one HTML file that doesn't need the network, with every resource it
+/// fetched inlined as a data URL and anything it didn't made absolute.
+pub fn save_single_file(doc: &BaseDocument, resources: Resources) -> String {
+	let base = doc.resolve_url("").to_string();
+	let html = inline_html(&doc.root_element().outer_html(), &base, resources);
+
+	// a JSON string can't end the script element early if its slashes are escaped
+	let state = saved_state(doc).to_string().replace("</", "<\\/");
+	let state = format!(
+		"<script id=\"blitz-saved-state\" type=\"application/json\">{state}</script>\n{RESTORE_SCRIPT}\n"
+	);
+
+	// appended last so the paths recorded for the body's children still point at them
+	let mut html = html;
+	match html.rfind("</body>") {
+		Some(at) => html.insert_str(at, &state),
+		None => html.push_str(&state),
+	}
+	format!("<!DOCTYPE html>\n{html}")
+}
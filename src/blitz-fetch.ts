@@ -6,11 +6,9 @@ let initted = false;
 let client: EpoxyClient | undefined;
 let clientPromiseResolve = () => {};
 let clientPromise = new Promise<void>(r => clientPromiseResolve = r);
-let inflight = 0;
 
-let cache = await caches.open("blitz-net");
-
-(self as any).deleteCache = () => { caches.delete("blitz-net"); location.reload(); }
+// responses used to be cached here forever, blitz has its own HTTP cache now
+caches.delete("blitz-net");
 
 export async function initBlitzNet(wisp: string) {
 	console.log("initting blitz net with", wisp);
@@ -36,25 +34,28 @@ export async function initBlitzNet(wisp: string) {
 	clientPromiseResolve();
 }
 
-export async function blitzFetch(req: Request): Promise<[string, Uint8Array]> {
+export async function blitzFetch(req: Request) {
 	await clientPromise;
 	if (!client) throw "client not initted";
-	inflight++;
-	try {
-		console.debug("[blitz-net]", req.method, req.url)
-
-		let res;
-		if (!(res = await cache.match(req))) {
-			res = await client.fetch(req.url, { method: req.method, headers: req.headers, body: req.body });
-			cache.put(req, res.clone());
-		}
-
-		return [res.url || req.url, new Uint8Array(await res.arrayBuffer())];
-	} finally {
-		inflight--;
+	req.signal.throwIfAborted();
+	console.debug("[blitz-net]", req.method, req.url)
+
+	// browsers won't let a Request carry cookies, so blitz sends them under another name
+	let headers = Object.fromEntries(req.headers);
+	let cookie = headers["x-blitz-cookie"];
+	delete headers["x-blitz-cookie"];
+	if (cookie) headers["cookie"] = cookie;
+
+	// blitz follows redirects itself so every hop goes through its cookie jar
+	let res = await client.fetch(req.url, { method: req.method, headers, body: req.body, signal: req.signal, redirect: "manual" });
+
+	// and a Response can't expose Set-Cookie, but epoxy keeps the raw headers around
+	let resHeaders = new Headers(res.headers);
+	let setCookie: string | string[] | undefined = (res as any).rawHeaders?.["set-cookie"];
+	if (setCookie) {
+		let cookies = Array.isArray(setCookie) ? setCookie : [setCookie];
+		resHeaders.set("x-blitz-set-cookie", cookies.map(encodeURIComponent).join(","));
 	}
-}
 
-export function blitzInflight(): boolean {
-	return inflight > 0;
+	return { url: res.url, status: res.status, statusText: res.statusText, headers: resHeaders, body: res.body };
 }
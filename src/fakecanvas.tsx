@@ -6,7 +6,6 @@ export interface ImageStream {
 }
 
 function rafPipe(stream: ImageStream): ReadableStream<VideoFrame> {
-	let scale = stream.scale;
 	return new ReadableStream({
 		async start(controller) {
 			while (true) {
@@ -15,6 +14,8 @@ function rafPipe(stream: ImageStream): ReadableStream<VideoFrame> {
 				if (done) break;
 				if (!value) continue;
 
+				// read every frame, the scale changes when the window moves between displays
+				let scale = stream.scale;
 				controller.enqueue(new VideoFrame(value, { timestamp: rafTime, displayWidth: value.width / scale, displayHeight: value.height / scale }));
 				value.close();
 			}
@@ -34,6 +35,7 @@ export function FakeCanvas(this: FC<{
 	pointer: (e: PointerEvent, x: number, y: number) => void,
 	scroll: (e: WheelEvent, x: number, y: number) => void,
 	key: (e: KeyboardEvent) => void,
+	drop: (files: File[], x: number, y: number) => void,
 }>) {
 	let stream = new MediaStream();
 	stream.addTrack(streamToTrack(rafPipe(this.stream)));
@@ -48,6 +50,13 @@ export function FakeCanvas(this: FC<{
 		this.scroll(e, x, y);
 	};
 
+	// the files can only be read while the event is being dispatched
+	let drop = (e: DragEvent) => {
+		e.preventDefault();
+		let { x, y } = this.root.getBoundingClientRect();
+		this.drop([...e.dataTransfer?.files || []], e.clientX - x, e.clientY - y);
+	};
+
 	this.cx.mount = () => {
 		this.focus.listen(() => this.root.focus());
 	}
@@ -68,6 +77,8 @@ export function FakeCanvas(this: FC<{
 			on:keydown={this.key}
 			on:keyup={this.key}
 			on:wheel={wheel}
+			on:dragover={(e: DragEvent) => e.preventDefault()}
+			on:drop={drop}
 		/>
 	)
 }
@@ -6,7 +6,7 @@ import init, { BlitzDocument, BlitzRenderer, BlitzShellProvider, type BlitzRende
 import blitz_wasm from "../blitz/pkg/blitz_dl.wasm?url";
 import { BlitzDomNode, createBlitzDomImpl, withHarnessDisabled } from "./blitz-dom";
 import { BlitzApp } from "./blitz-main";
-import { blitzFetch, blitzInflight, initBlitzNet } from "./blitz-fetch";
+import { blitzFetch, initBlitzNet } from "./blitz-fetch";
 
 import initialHtml from "./initial.html?raw";
 import flavortownHtml from "./flavortown.html?raw";
@@ -70,6 +70,7 @@ function App(this: FC<{
 	let pointer: [PointerEvent, number, number][] = [];
 	let wheel: [WheelEvent, number, number][] = [];
 	let key: [KeyboardEvent][] = [];
+	let drop: [File[], number, number][] = [];
 
 	let screen: OffscreenCanvas | undefined;
 	let [renderer, doc, events] = this.ret;
@@ -81,12 +82,16 @@ function App(this: FC<{
 		let time = performance.now() / 1000;
 
 		doc.resolve(time)
+		doc.dispatch_net_events(events);
+		doc.apply_picked_files(events);
 
 		for (let ev of pointer.splice(0)) doc.event(events, BlitzDocument.event_pointer(...ev))
 		for (let ev of wheel.splice(0)) doc.event(events, BlitzDocument.event_wheel(...ev))
 		for (let ev of key.splice(0)) doc.event(events, BlitzDocument.event_keyboard(...ev))
+		for (let ev of drop.splice(0)) doc.drop_files(events, ...ev)
 
-		renderer.render(doc, blitzInflight(), time);
+		// nothing changed, so keep showing the last frame
+		if (!renderer.render(doc, doc.loading, time)) return { done: false };
 
 		return { value: screen.transferToImageBitmap(), done: false };
 	}) as any;
@@ -117,19 +122,28 @@ function App(this: FC<{
 			debounce = setTimeout(() => {
 				debounce = undefined;
 				this.dims = [html.clientWidth, html.clientHeight];
-			}, 500)
+			}, 50)
 		})
+
+		let watchScale = () => {
+			matchMedia(`(resolution: ${window.devicePixelRatio}dppx)`).addEventListener("change", () => {
+				SCALE = Math.ceil(window.devicePixelRatio);
+				stream.scale = SCALE;
+				this.dims = [html.clientWidth, html.clientHeight];
+				watchScale();
+			}, { once: true });
+		};
+		watchScale();
 	}
 	this.cx.mount = init;
 
-	use(this.dims).constrain(this).listen(async ([width, height]) => {
-		withHarnessDisabled(() => this.state = "renderer-init");
-		screen = undefined;
-
-		console.log("creating pipeline with dims", width, height);
+	use(this.dims).constrain(this).listen(([width, height]) => {
+		console.log("resizing to dims", width, height, "at scale", SCALE);
 		try {
-			let currentScreen = new OffscreenCanvas(width * SCALE, height * SCALE);
-			await renderer.resize(doc, currentScreen, SCALE);
+			let currentScreen = screen || new OffscreenCanvas(1, 1);
+			currentScreen.width = width * SCALE;
+			currentScreen.height = height * SCALE;
+			renderer.resize(doc, currentScreen, SCALE);
 
 			screen = currentScreen;
 
@@ -149,7 +163,7 @@ function App(this: FC<{
 		<div>
 			{use(this.state).map(x => x === "net-proxy-fail").and(<ProxyFail wisp={use(this.wisp)} then={init} />)}
 			{use(this.state).map(x => !["rendering", "net-proxy-fail"].includes(x)).and(_ => <SvgState state={this.state} />)}
-			{use(this.state).map(x => x === "rendering").and(<FakeCanvas stream={stream} focus={focusCanvas} pointer={onEv(pointer)} scroll={onEv(wheel)} key={onEv(key)} />)}
+			{use(this.state).map(x => x === "rendering").and(<FakeCanvas stream={stream} focus={focusCanvas} pointer={onEv(pointer)} scroll={onEv(wheel)} key={onEv(key)} drop={onEv(drop)} />)}
 		</div>
 	)
 }
@@ -162,9 +176,19 @@ try {
 			navigator.clipboard.writeText(text)
 				.then(() => console.debug("[blitz-shell] wrote to clipboard"))
 				.catch((e) => console.warn("[blitz-shell] failed to write to clipboard", e));
-		}
+		},
+		(multiple: boolean, accept: string[]) => new Promise<File[]>((resolve) => {
+			let input = document.createElement("input");
+			input.type = "file";
+			input.multiple = multiple;
+			input.accept = accept.join(",");
+			input.addEventListener("change", () => resolve([...input.files || []]));
+			input.addEventListener("cancel", () => resolve([]));
+			input.click();
+		})
 	);
 	let renderer = await BlitzRenderer.new(flavortown ? flavortownHtml : initialHtml, "https://dreamland.js.org/", blitzFetch, shell, new OffscreenCanvas(1, 1), 1);
+	(self as any).deleteCache = () => { renderer[1].clear_cache(); location.reload(); };
 	document.body.replaceWith(<App wisp="wss://anura.pro/" ret={renderer} ready={() => {
 		if (flavortown) return;
 